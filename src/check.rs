@@ -0,0 +1,275 @@
+use std::{collections::HashSet, net::SocketAddr, path::PathBuf};
+
+use crate::{
+    config,
+    endpoint::{transmitter, EndpointSettings},
+    mavlink::definitions::{self, ParseError},
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum Problem {
+    #[error("Failed to load the MAVLink definitions")]
+    Definitions(#[source] ParseError),
+    #[error("The endpoint name '{0}' is used more than once")]
+    DuplicateName(String),
+    #[error("[{0}] The address {2} collides with endpoint '{1}'")]
+    AddressCollision(String, String, SocketAddr),
+    #[error("[{0}] Can't bind to {1}")]
+    Unbindable(String, SocketAddr, #[source] std::io::Error),
+    #[error("[{0}] The device {} is also used by endpoint '{1}'", .2.display())]
+    DeviceCollision(String, String, PathBuf),
+    #[error("[{0}] Can't find the device {}", .1.display())]
+    MissingDevice(String, PathBuf, #[source] std::io::Error),
+    #[error("{0} refers to the unknown endpoint '{1}'")]
+    UnknownEndpoint(String, String),
+}
+
+/// The outcome of checking a configuration without starting the router.
+#[derive(Debug)]
+pub struct Report {
+    /// The number of targeted messages in the definitions, if they could be loaded.
+    pub targeted_messages: Option<usize>,
+    pub problems: Vec<Problem>,
+}
+
+pub fn check(settings: &config::Settings) -> Report {
+    let mut problems = Vec::new();
+
+    let targeted_messages =
+        match definitions::try_get_definitions_from_xml(settings.definitions.clone()) {
+            Ok(definitions) => Some(definitions.offsets.len()),
+            Err(e) => {
+                problems.push(Problem::Definitions(e));
+                None
+            }
+        };
+
+    let mut names = HashSet::new();
+    for endpoint in &settings.endpoints {
+        if !names.insert(endpoint.name.as_str()) {
+            problems.push(Problem::DuplicateName(endpoint.name.clone()));
+        }
+    }
+
+    check_addresses(&settings.endpoints, &mut problems);
+    check_references(settings, &names, &mut problems);
+
+    Report {
+        targeted_messages,
+        problems,
+    }
+}
+
+fn address(kind: &transmitter::Settings) -> Option<(bool, SocketAddr)> {
+    match kind {
+        transmitter::Settings::Udp(settings) => Some((false, settings.address)),
+        transmitter::Settings::Tcp(settings) => Some((true, settings.address)),
+        transmitter::Settings::Serial(_) => None,
+    }
+}
+
+fn device(kind: &transmitter::Settings) -> Option<&PathBuf> {
+    match kind {
+        transmitter::Settings::Serial(settings) => Some(&settings.path),
+        _ => None,
+    }
+}
+
+fn collides(a: SocketAddr, b: SocketAddr) -> bool {
+    a.port() == b.port() && (a.ip() == b.ip() || a.ip().is_unspecified() || b.ip().is_unspecified())
+}
+
+fn bind(kind: &transmitter::Settings) -> std::io::Result<()> {
+    match kind {
+        transmitter::Settings::Udp(settings) => {
+            std::net::UdpSocket::bind(settings.address).map(drop)
+        }
+        transmitter::Settings::Tcp(settings) => {
+            std::net::TcpListener::bind(settings.address).map(drop)
+        }
+        // Opening the device could disturb whatever is using it right now
+        transmitter::Settings::Serial(_) => Ok(()),
+    }
+}
+
+fn check_addresses(endpoints: &[EndpointSettings], problems: &mut Vec<Problem>) {
+    for (i, endpoint) in endpoints.iter().enumerate() {
+        if let Some(path) = device(&endpoint.kind) {
+            check_device(endpoint, path, &endpoints[..i], problems);
+            continue;
+        }
+        let Some((is_tcp, addr)) = address(&endpoint.kind) else {
+            continue;
+        };
+        // Multicast groups can be joined by several sockets on the same port
+        let collision = endpoints[..i].iter().find(|other| {
+            address(&other.kind).is_some_and(|(other_is_tcp, other_addr)| {
+                is_tcp == other_is_tcp && !addr.ip().is_multicast() && collides(addr, other_addr)
+            })
+        });
+        match collision {
+            Some(other) => problems.push(Problem::AddressCollision(
+                endpoint.name.clone(),
+                other.name.clone(),
+                addr,
+            )),
+            None => {
+                if let Err(e) = bind(&endpoint.kind) {
+                    problems.push(Problem::Unbindable(endpoint.name.clone(), addr, e));
+                }
+            }
+        }
+    }
+}
+
+fn check_device(
+    endpoint: &EndpointSettings,
+    path: &PathBuf,
+    previous: &[EndpointSettings],
+    problems: &mut Vec<Problem>,
+) {
+    match previous
+        .iter()
+        .find(|other| device(&other.kind) == Some(path))
+    {
+        Some(other) => problems.push(Problem::DeviceCollision(
+            endpoint.name.clone(),
+            other.name.clone(),
+            path.clone(),
+        )),
+        None => {
+            if let Err(e) = std::fs::metadata(path) {
+                problems.push(Problem::MissingDevice(
+                    endpoint.name.clone(),
+                    path.clone(),
+                    e,
+                ));
+            }
+        }
+    }
+}
+
+fn check_references(
+    settings: &config::Settings,
+    names: &HashSet<&str>,
+    problems: &mut Vec<Problem>,
+) {
+    let references = settings
+        .redundant_links
+        .iter()
+        .flat_map(|group| &group.endpoints)
+        .map(|name| ("A redundant link group".to_string(), name))
+        .chain(settings.link_groups.iter().flat_map(|group| {
+            std::iter::once(&group.primary)
+                .chain(&group.backups)
+                .map(|name| (format!("The link group '{}'", group.name), name))
+        }))
+        .chain(settings.id_locks.iter().map(|lock| {
+            (
+                format!("The lock of sys_id {}", lock.sys_id),
+                &lock.endpoint,
+            )
+        }));
+    for (context, name) in references {
+        if !names.contains(name.as_str()) {
+            problems.push(Problem::UnknownEndpoint(context, name.clone()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{endpoint::transmitter::udp, router};
+    use std::net::Ipv4Addr;
+
+    fn endpoint(name: &str, address: SocketAddr) -> EndpointSettings {
+        EndpointSettings::new(name, transmitter::Settings::Udp(udp::Settings { address }))
+    }
+
+    fn settings(endpoints: Vec<EndpointSettings>) -> config::Settings {
+        config::Settings {
+            endpoints,
+            ..config::Settings::new("does-not-exist.xml")
+        }
+    }
+
+    fn free_address() -> std::io::Result<SocketAddr> {
+        std::net::UdpSocket::bind((Ipv4Addr::LOCALHOST, 0))?.local_addr()
+    }
+
+    #[test]
+    fn test_check_reports_missing_definitions() {
+        let report = check(&settings(Vec::new()));
+
+        assert_eq!(report.targeted_messages, None);
+        assert!(matches!(report.problems[..], [Problem::Definitions(_)]));
+    }
+
+    #[test]
+    fn test_check_reports_duplicate_names_and_collisions() -> std::io::Result<()> {
+        let address = free_address()?;
+        let any = SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), address.port());
+        let report = check(&settings(vec![
+            endpoint("gcs", address),
+            endpoint("gcs", any),
+        ]));
+
+        assert!(matches!(
+            report.problems[..],
+            [
+                Problem::Definitions(_),
+                Problem::DuplicateName(_),
+                Problem::AddressCollision(..)
+            ]
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_reports_unbindable_addresses() -> std::io::Result<()> {
+        let socket = std::net::UdpSocket::bind((Ipv4Addr::LOCALHOST, 0))?;
+        let report = check(&settings(vec![endpoint("gcs", socket.local_addr()?)]));
+
+        assert!(matches!(report.problems[1], Problem::Unbindable(..)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_reports_serial_devices() {
+        let serial = |name: &str, path: &str| {
+            EndpointSettings::new(
+                name,
+                transmitter::Settings::Serial(transmitter::serial::Settings::new(path)),
+            )
+        };
+        let report = check(&settings(vec![
+            serial("fc", "/dev/null"),
+            serial("radio", "/dev/null"),
+            serial("companion", "/does/not/exist"),
+        ]));
+
+        assert!(matches!(
+            report.problems[..],
+            [
+                Problem::Definitions(_),
+                Problem::DeviceCollision(..),
+                Problem::MissingDevice(..)
+            ]
+        ));
+    }
+
+    #[test]
+    fn test_check_reports_unknown_endpoints() -> std::io::Result<()> {
+        let mut settings = settings(vec![endpoint("radio", free_address()?)]);
+        settings.redundant_links.push(router::dedup::Settings {
+            endpoints: vec!["radio".to_string(), "lte".to_string()],
+            window_ms: 500,
+        });
+        let report = check(&settings);
+
+        assert_eq!(report.problems.len(), 2);
+        assert!(matches!(&report.problems[1], Problem::UnknownEndpoint(_, name) if name == "lte"));
+        Ok(())
+    }
+}
@@ -0,0 +1,360 @@
+use ini::Ini;
+use std::{
+    collections::BTreeMap,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    path::{Path, PathBuf},
+};
+
+use super::Settings;
+use crate::{
+    endpoint::{
+        transmitter::{self, serial, tcp, udp},
+        EndpointSettings,
+    },
+    router,
+};
+
+// mavlink-router listens for TCP clients on this port, unless it is set to 0.
+const DEFAULT_TCP_SERVER_PORT: u16 = 5760;
+// The baud rate of UART endpoints which don't set one in mavlink-router.
+const DEFAULT_BAUD: u32 = 115200;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ImportError {
+    #[error("Failed to read the mavlink-router configuration")]
+    Read(#[from] ini::Error),
+    #[error("[{0}] Missing option '{1}'")]
+    MissingOption(String, &'static str),
+    #[error("[{0}] Invalid value '{2}' for option '{1}'")]
+    InvalidValue(String, String, String),
+}
+
+/// Settings converted from a mavlink-router configuration.
+#[derive(Debug)]
+pub struct Import {
+    pub settings: Settings,
+    /// Options and sections which have no equivalent and were ignored.
+    pub unsupported: Vec<String>,
+}
+
+// The options of a section, with their keys in lower case since mavlink-router ignores case.
+struct Section {
+    name: String,
+    options: BTreeMap<String, String>,
+}
+
+impl Section {
+    fn take(&mut self, key: &'static str) -> Option<String> {
+        self.options.remove(&key.to_lowercase())
+    }
+
+    fn parse<T: std::str::FromStr>(&mut self, key: &'static str) -> Result<Option<T>, ImportError> {
+        self.take(key)
+            .map(|value| {
+                value.parse().map_err(|_| {
+                    ImportError::InvalidValue(self.name.clone(), key.to_string(), value)
+                })
+            })
+            .transpose()
+    }
+
+    fn require<T: std::str::FromStr>(&mut self, key: &'static str) -> Result<T, ImportError> {
+        self.parse(key)?
+            .ok_or_else(|| ImportError::MissingOption(self.name.clone(), key))
+    }
+
+    // Reports the options which weren't used for the conversion.
+    fn report_rest(self, unsupported: &mut Vec<String>) {
+        for (key, value) in self.options {
+            unsupported.push(format!("[{}] {} = {}", self.name, key, value));
+        }
+    }
+}
+
+pub fn import(path: &Path) -> Result<Import, ImportError> {
+    let ini = Ini::load_from_file_noescape(path)?;
+    convert(&ini)
+}
+
+fn convert(ini: &Ini) -> Result<Import, ImportError> {
+    // mavlink-router doesn't need the message definitions, they have to be given separately
+    let mut settings = Settings::new(PathBuf::new());
+    let mut unsupported = Vec::new();
+    let mut tcp_server_port = DEFAULT_TCP_SERVER_PORT;
+    let mut groups: BTreeMap<String, Vec<String>> = BTreeMap::new();
+
+    for (name, properties) in ini.iter() {
+        let name = match name {
+            Some(name) => name.trim(),
+            None => continue,
+        };
+        let mut section = Section {
+            name: name.to_string(),
+            options: properties
+                .iter()
+                .map(|(key, value)| (key.to_lowercase(), value.trim().to_string()))
+                .collect(),
+        };
+        let (kind, endpoint_name) = match name.split_once(char::is_whitespace) {
+            Some((kind, endpoint_name)) => (kind, endpoint_name.trim()),
+            None => (name, ""),
+        };
+
+        let endpoint = match kind.to_lowercase().as_str() {
+            "general" => {
+                if let Some(port) = section.parse("TcpServerPort")? {
+                    tcp_server_port = port;
+                }
+                if section.parse::<bool>("ReportStats")?.unwrap_or(false) {
+                    settings.link_stats_interval_ms = Some(1000);
+                }
+                None
+            }
+            "udpendpoint" => udp_endpoint(endpoint_name, &mut section)?,
+            "tcpendpoint" => {
+                unsupported.push(format!("[{}] TCP client endpoints", name));
+                section.options.clear();
+                None
+            }
+            "uartendpoint" => Some(uart_endpoint(
+                endpoint_name,
+                &mut section,
+                &mut unsupported,
+            )?),
+            _ => {
+                unsupported.push(format!("[{}] Unknown section", name));
+                section.options.clear();
+                None
+            }
+        };
+
+        if let Some(endpoint) = endpoint {
+            if let Some(group) = section.take("Group") {
+                groups.entry(group).or_default().push(endpoint.name.clone());
+            }
+            settings.endpoints.push(endpoint);
+        } else if section
+            .take("Mode")
+            .is_some_and(|m| m.eq_ignore_ascii_case("normal"))
+        {
+            unsupported.push(format!("[{}] UDP client endpoints", name));
+            section.options.clear();
+        }
+        section.report_rest(&mut unsupported);
+    }
+
+    if tcp_server_port != 0 {
+        settings.endpoints.push(EndpointSettings::new(
+            "tcp-server",
+            transmitter::Settings::Tcp(tcp::Settings {
+                address: SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), tcp_server_port),
+            }),
+        ));
+    }
+
+    // Endpoints of a group are links to the same systems in mavlink-router
+    settings.redundant_links = groups
+        .into_values()
+        .filter(|endpoints| endpoints.len() > 1)
+        .map(|endpoints| router::dedup::Settings {
+            endpoints,
+            window_ms: 500,
+        })
+        .collect();
+
+    Ok(Import {
+        settings,
+        unsupported,
+    })
+}
+
+fn udp_endpoint(
+    name: &str,
+    section: &mut Section,
+) -> Result<Option<EndpointSettings>, ImportError> {
+    // Only servers wait for peers, clients send to a fixed address
+    let mode = section.take("Mode").unwrap_or_default().to_lowercase();
+    if mode != "server" && mode != "eavesdropping" {
+        section.options.insert("mode".to_string(), mode);
+        return Ok(None);
+    }
+
+    let ip: IpAddr = section.require("Address")?;
+    let port = section.parse("Port")?.unwrap_or(14550);
+    Ok(Some(EndpointSettings::new(
+        name,
+        transmitter::Settings::Udp(udp::Settings {
+            address: SocketAddr::new(ip, port),
+        }),
+    )))
+}
+
+fn uart_endpoint(
+    name: &str,
+    section: &mut Section,
+    unsupported: &mut Vec<String>,
+) -> Result<EndpointSettings, ImportError> {
+    let mut settings = serial::Settings::new(section.require::<PathBuf>("Device")?);
+    settings.baud = DEFAULT_BAUD;
+    if let Some(bauds) = section.take("Baud") {
+        // mavlink-router tries each of a list of rates, while serial endpoints use a fixed one
+        let mut rates = bauds.split(',').map(str::trim);
+        let first = rates.next().unwrap_or_default();
+        settings.baud = first.parse().map_err(|_| {
+            ImportError::InvalidValue(section.name.clone(), "Baud".to_string(), bauds.clone())
+        })?;
+        if rates.next().is_some() {
+            unsupported.push(format!(
+                "[{}] baud = {}, only {} is used",
+                section.name, bauds, first
+            ));
+        }
+    }
+    Ok(EndpointSettings::new(
+        name,
+        transmitter::Settings::Serial(settings),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn import_str(config: &str) -> Result<Import, ImportError> {
+        convert(&Ini::load_from_str_noescape(config).expect("valid INI"))
+    }
+
+    #[test]
+    fn test_import_udp_servers_and_tcp_server() -> Result<(), ImportError> {
+        let import = import_str(
+            "
+            [General]
+            TcpServerPort = 5790
+            ReportStats = true
+
+            [UdpEndpoint gcs]
+            Mode = Server
+            Address = 0.0.0.0
+            Port = 14550
+            ",
+        )?;
+
+        let endpoints = &import.settings.endpoints;
+        assert_eq!(endpoints.len(), 2);
+        assert_eq!(endpoints[0].name, "gcs");
+        assert_eq!(endpoints[0].kind.to_string(), "udp://0.0.0.0:14550");
+        assert_eq!(endpoints[1].kind.to_string(), "tcp://0.0.0.0:5790");
+        assert_eq!(import.settings.link_stats_interval_ms, Some(1000));
+        assert!(import.unsupported.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_import_reports_unsupported_options() -> Result<(), ImportError> {
+        let import = import_str(
+            "
+            [General]
+            TcpServerPort = 0
+            MavlinkDialect = auto
+
+            [UartEndpoint alpha]
+            Device = /dev/ttyS0
+            Baud = 57600,115200
+            FlowControl = true
+
+            [UdpEndpoint client]
+            Mode = Normal
+            Address = 10.0.0.2
+            Port = 14550
+
+            [UdpEndpoint gcs]
+            mode = eavesdropping
+            address = 0.0.0.0
+            BlockMsgIdOut = 30
+            ",
+        )?;
+
+        assert_eq!(import.settings.endpoints.len(), 2);
+        assert_eq!(
+            import.unsupported,
+            vec![
+                "[General] mavlinkdialect = auto",
+                "[UartEndpoint alpha] baud = 57600,115200, only 57600 is used",
+                "[UartEndpoint alpha] flowcontrol = true",
+                "[UdpEndpoint client] UDP client endpoints",
+                "[UdpEndpoint gcs] blockmsgidout = 30",
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_import_uart_endpoints() -> Result<(), ImportError> {
+        let import = import_str(
+            "
+            [General]
+            TcpServerPort = 0
+
+            [UartEndpoint fc]
+            Device = /dev/ttyACM0
+            Baud = 921600
+
+            [UartEndpoint radio]
+            Device = /dev/ttyUSB0
+            ",
+        )?;
+
+        let endpoints: Vec<_> = import
+            .settings
+            .endpoints
+            .iter()
+            .map(|e| format!("{}: {}", e.name, e.kind))
+            .collect();
+        assert_eq!(
+            endpoints,
+            vec![
+                "fc: serial:///dev/ttyACM0?baud=921600",
+                "radio: serial:///dev/ttyUSB0?baud=115200",
+            ]
+        );
+        assert!(import.unsupported.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_import_groups_become_redundant_links() -> Result<(), ImportError> {
+        let import = import_str(
+            "
+            [UdpEndpoint radio]
+            Mode = Server
+            Address = 0.0.0.0
+            Port = 14550
+            Group = vehicle
+
+            [UdpEndpoint lte]
+            Mode = Server
+            Address = 0.0.0.0
+            Port = 14551
+            Group = vehicle
+            ",
+        )?;
+
+        assert_eq!(
+            import.settings.redundant_links[0].endpoints,
+            vec!["radio", "lte"]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_import_rejects_invalid_values() {
+        let result = import_str(
+            "
+            [UdpEndpoint gcs]
+            Mode = Server
+            Address = localhost
+            ",
+        );
+        assert!(matches!(result, Err(ImportError::InvalidValue(..))));
+    }
+}
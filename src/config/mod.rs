@@ -0,0 +1,249 @@
+use config::Config;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::path;
+
+pub mod mavlink_router;
+
+use crate::{
+    control,
+    endpoint::{target_database, EndpointSettings},
+    metrics, router,
+};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Settings {
+    /// The path to the XML definition file.
+    pub definitions: path::PathBuf,
+    pub endpoints: Vec<EndpointSettings>,
+    /// Restricts IDs to the endpoints and addresses they may be used from.
+    #[serde(default)]
+    pub id_locks: Vec<target_database::IdLock>,
+    /// Groups of endpoints which are redundant links to the same vehicle.
+    #[serde(default)]
+    pub redundant_links: Vec<router::dedup::Settings>,
+    /// Groups of endpoints where targeted traffic only uses the healthiest link.
+    #[serde(default)]
+    pub link_groups: Vec<router::failover::Settings>,
+    /// The router's own component ID, which it announces with heartbeats if set.
+    #[serde(default)]
+    pub identity: Option<router::identity::Settings>,
+    /// Detects messages circulating between chained routers, disabled if not set.
+    #[serde(default)]
+    pub loop_detection: Option<router::loop_detection::Settings>,
+    /// How often to log the link statistics of all endpoints, disabled if not set.
+    #[serde(default)]
+    pub link_stats_interval_ms: Option<u64>,
+    /// Serves metrics for Prometheus over HTTP, disabled if not set.
+    #[serde(default)]
+    pub metrics: Option<metrics::Settings>,
+    /// Accepts requests inspecting and steering the running router, disabled if not set.
+    #[serde(default)]
+    pub control: Option<control::Settings>,
+}
+
+impl Settings {
+    /// Settings without any endpoints or optional features.
+    pub fn new(definitions: impl Into<path::PathBuf>) -> Self {
+        Self {
+            definitions: definitions.into(),
+            endpoints: Vec::new(),
+            id_locks: Vec::new(),
+            redundant_links: Vec::new(),
+            link_groups: Vec::new(),
+            identity: None,
+            loop_detection: None,
+            link_stats_interval_ms: None,
+            metrics: None,
+            control: None,
+        }
+    }
+
+    /// Loads the settings from a file, where `.conf` files are read as mavlink-router
+    /// configurations.
+    pub fn load(config: &path::Path) -> Result<Self, config::ConfigError> {
+        let builder = if config.extension().is_some_and(|ext| ext == "conf") {
+            let import = mavlink_router::import(config)
+                .map_err(|e| config::ConfigError::Foreign(Box::new(e)))?;
+            for option in &import.unsupported {
+                warn!(
+                    "Ignoring mavlink-router option without equivalent: {}",
+                    option
+                );
+            }
+            Config::builder().add_source(Config::try_from(&import.settings)?)
+        } else {
+            Config::builder().add_source(config::File::from(config))
+        };
+        builder
+            .add_source(config::Environment::with_prefix("MAVLINK_SHOUTER"))
+            .build()?
+            .try_deserialize()
+    }
+}
+
+/// Settings given on the command line, which take precedence over the configuration file.
+#[derive(Debug, Clone, Default)]
+pub struct Overrides {
+    pub definitions: Option<path::PathBuf>,
+    /// Replace the endpoints of the same name, the others are added.
+    pub endpoints: Vec<EndpointSettings>,
+    /// Drop all configured endpoints in favor of the given ones.
+    pub replace_endpoints: bool,
+}
+
+impl Overrides {
+    /// Loads the settings from the configuration file if given, and applies the overrides.
+    pub fn load(&self, config: Option<&path::Path>) -> Result<Settings, config::ConfigError> {
+        let mut settings = match (config, &self.definitions) {
+            (Some(config), _) => Settings::load(config)?,
+            (None, Some(definitions)) => Settings::new(definitions),
+            (None, None) => {
+                return Err(config::ConfigError::Message(
+                    "The definitions are required without a configuration file".to_string(),
+                ))
+            }
+        };
+        self.apply(&mut settings);
+        // mavlink-router configurations don't name the definitions
+        if settings.definitions.as_os_str().is_empty() {
+            return Err(config::ConfigError::Message(
+                "The configuration has no message definitions, use --definitions".to_string(),
+            ));
+        }
+        Ok(settings)
+    }
+
+    pub fn apply(&self, settings: &mut Settings) {
+        if let Some(definitions) = &self.definitions {
+            settings.definitions = definitions.clone();
+        }
+        if self.replace_endpoints {
+            settings.endpoints.clear();
+        }
+        for endpoint in &self.endpoints {
+            settings.endpoints.retain(|e| e.name != endpoint.name);
+            settings.endpoints.push(endpoint.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::endpoint::rate_limiter;
+    use crate::endpoint::transmitter::{self, tcp, udp};
+
+    use super::*;
+    use std::net::{IpAddr, SocketAddr};
+    use std::path::Path;
+
+    #[test]
+    fn test_load_settings() -> Result<(), config::ConfigError> {
+        let config_path =
+            Path::new(std::env!("CARGO_MANIFEST_DIR")).join("tests/resources/config.yml");
+        let settings = Settings::load(config_path.as_path())?;
+        assert_eq!(
+            settings.definitions,
+            path::PathBuf::from("tests/fixtures/definitions.xml")
+        );
+        assert_eq!(settings.endpoints.len(), 2);
+        assert_eq!(settings.endpoints[0].name, "udp");
+        assert_eq!(
+            settings.endpoints[0].kind,
+            transmitter::Settings::Udp(udp::Settings {
+                address: SocketAddr::new(IpAddr::V4("127.0.0.1".parse().unwrap()), 14550)
+            })
+        );
+        assert_eq!(
+            settings.endpoints[0].rate_limits,
+            vec![rate_limiter::Settings {
+                message_id: 30,
+                max_rate: 5.0,
+                per_component: false,
+            }]
+        );
+        assert_eq!(settings.endpoints[1].name, "tcp");
+        assert_eq!(
+            settings.endpoints[1].kind,
+            transmitter::Settings::Tcp(tcp::Settings {
+                address: SocketAddr::new(IpAddr::V4("127.0.0.1".parse().unwrap()), 14551)
+            })
+        );
+        assert!(settings.endpoints[1].rate_limits.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_overrides_replace_endpoints_by_name() -> Result<(), config::ConfigError> {
+        let config_path =
+            Path::new(std::env!("CARGO_MANIFEST_DIR")).join("tests/resources/config.yml");
+        let overrides = Overrides {
+            definitions: Some("other.xml".into()),
+            endpoints: vec![
+                "udp=udp://127.0.0.1:14560".parse().unwrap(),
+                "gcs=tcp://:5760".parse().unwrap(),
+            ],
+            ..Default::default()
+        };
+        let settings = overrides.load(Some(&config_path))?;
+
+        assert_eq!(settings.definitions, path::PathBuf::from("other.xml"));
+        let endpoints: Vec<_> = settings
+            .endpoints
+            .iter()
+            .map(|e| format!("{}: {}", e.name, e.kind))
+            .collect();
+        assert_eq!(
+            endpoints,
+            vec![
+                "tcp: tcp://127.0.0.1:14551",
+                "udp: udp://127.0.0.1:14560",
+                "gcs: tcp://0.0.0.0:5760",
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_overrides_replace_all_endpoints() -> Result<(), config::ConfigError> {
+        let config_path =
+            Path::new(std::env!("CARGO_MANIFEST_DIR")).join("tests/resources/config.yml");
+        let overrides = Overrides {
+            endpoints: vec!["fc=serial:///dev/ttyACM0?baud=921600".parse().unwrap()],
+            replace_endpoints: true,
+            ..Default::default()
+        };
+        let settings = overrides.load(Some(&config_path))?;
+
+        assert_eq!(settings.endpoints.len(), 1);
+        assert_eq!(settings.endpoints[0].name, "fc");
+        Ok(())
+    }
+
+    #[test]
+    fn test_overrides_without_config() {
+        assert!(Overrides::default().load(None).is_err());
+
+        let overrides = Overrides {
+            definitions: Some("definitions.xml".into()),
+            endpoints: vec!["tcp://:5760".parse().unwrap()],
+            ..Default::default()
+        };
+        let settings = overrides.load(None).unwrap();
+        assert_eq!(settings.endpoints.len(), 1);
+    }
+
+    #[test]
+    fn test_overrides_require_definitions_for_mavlink_router() {
+        let config_path =
+            Path::new(std::env!("CARGO_MANIFEST_DIR")).join("tests/resources/mavlink-router.conf");
+        assert!(Overrides::default().load(Some(&config_path)).is_err());
+
+        let overrides = Overrides {
+            definitions: Some("definitions.xml".into()),
+            ..Default::default()
+        };
+        let settings = overrides.load(Some(&config_path)).unwrap();
+        assert_eq!(settings.endpoints[0].name, "fc");
+    }
+}
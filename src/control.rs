@@ -0,0 +1,201 @@
+use serde::{Deserialize, Serialize};
+use std::{net::SocketAddr, path::PathBuf};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Settings {
+    /// The Unix socket the control API listens on.
+    pub path: PathBuf,
+}
+
+/// A request to the control API, sent as one JSON object per line.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "kebab-case")]
+pub enum Request {
+    /// Lists all endpoints with their state.
+    List,
+    /// Lists the learned and static routes of an endpoint.
+    Routes {
+        endpoint: String,
+    },
+    AddRoute(Route),
+    RemoveRoute(Route),
+    /// Resumes routing to and from an endpoint.
+    Enable {
+        endpoint: String,
+    },
+    /// Stops routing to and from an endpoint, without closing its sockets.
+    Disable {
+        endpoint: String,
+    },
+    /// Resets the link statistics of an endpoint, or of all endpoints if not set.
+    ResetStats {
+        endpoint: Option<String>,
+    },
+}
+
+/// A static route, which sends messages for a component to an address of an endpoint.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Route {
+    pub endpoint: String,
+    pub sys_id: u8,
+    pub comp_id: u8,
+    pub address: SocketAddr,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EndpointInfo {
+    pub name: String,
+    /// The URL of the endpoint, e.g. `udp://0.0.0.0:14550`.
+    pub url: String,
+    pub enabled: bool,
+    pub targets: usize,
+    pub received: u64,
+    pub lost: u64,
+    pub invalid_senders: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RouteInfo {
+    pub sys_id: u8,
+    pub comp_id: u8,
+    pub address: SocketAddr,
+    /// How long ago the component was last heard from the address, not set for static routes.
+    pub last_seen_ms: Option<u64>,
+}
+
+/// The reply to a request, sent as one JSON object per line.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Response {
+    Endpoints(Vec<EndpointInfo>),
+    Routes(Vec<RouteInfo>),
+    Done,
+    Error(String),
+}
+
+#[cfg(unix)]
+pub use server::{request, Server};
+
+#[cfg(unix)]
+mod server {
+    use log::{debug, info, warn};
+    use std::{io, path::Path};
+    use tokio::{
+        io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+        net::{UnixListener, UnixStream},
+    };
+
+    use super::{Request, Response, Settings};
+    use crate::{log_error::LogError, Handle};
+
+    /// Listens for control requests on a Unix socket.
+    pub struct Server {
+        listener: UnixListener,
+    }
+
+    impl Server {
+        /// Binds the socket right away, replacing a stale socket left behind by a previous run.
+        pub fn bind(settings: &Settings) -> io::Result<Self> {
+            if std::os::unix::net::UnixStream::connect(&settings.path).is_ok() {
+                return Err(io::Error::new(
+                    io::ErrorKind::AddrInUse,
+                    format!("{} is in use by another process", settings.path.display()),
+                ));
+            }
+            if settings.path.exists() {
+                std::fs::remove_file(&settings.path)?;
+            }
+            let listener = UnixListener::bind(&settings.path)?;
+            info!(
+                "Listening for control requests on {}",
+                settings.path.display()
+            );
+            Ok(Self { listener })
+        }
+
+        pub fn start(self, handle: Handle) {
+            tokio::spawn(async move {
+                loop {
+                    let stream = match self.listener.accept().await {
+                        Ok((stream, _)) => stream,
+                        Err(e) => {
+                            debug!("Error accepting control connection: {}", e);
+                            continue;
+                        }
+                    };
+                    let handle = handle.clone();
+                    tokio::spawn(async move {
+                        serve(stream, handle).await.log_error();
+                    });
+                }
+            });
+        }
+    }
+
+    async fn serve(stream: UnixStream, handle: Handle) -> io::Result<()> {
+        let (reader, mut writer) = stream.into_split();
+        let mut lines = BufReader::new(reader).lines();
+        while let Some(line) = lines.next_line().await? {
+            let response = match serde_json::from_str(&line) {
+                Ok(request) => {
+                    debug!("Control request: {:?}", request);
+                    handle.control(request).await
+                }
+                Err(e) => {
+                    warn!("Invalid control request: {}", e);
+                    Response::Error(format!("Invalid request: {}", e))
+                }
+            };
+            let mut response = serde_json::to_string(&response)?;
+            response.push('\n');
+            writer.write_all(response.as_bytes()).await?;
+        }
+        Ok(())
+    }
+
+    /// Sends a request to the control API of a running router.
+    pub async fn request(path: &Path, request: &Request) -> io::Result<Response> {
+        let stream = UnixStream::connect(path).await?;
+        let (reader, mut writer) = stream.into_split();
+        let mut request = serde_json::to_string(request)?;
+        request.push('\n');
+        writer.write_all(request.as_bytes()).await?;
+
+        let line = BufReader::new(reader)
+            .lines()
+            .next_line()
+            .await?
+            .ok_or_else(|| io::Error::new(io::ErrorKind::UnexpectedEof, "No response"))?;
+        Ok(serde_json::from_str(&line)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_request_format() -> serde_json::Result<()> {
+        let request: Request = serde_json::from_str(
+            r#"{"command": "add-route", "endpoint": "gcs", "sys_id": 1, "comp_id": 1, "address": "127.0.0.1:14550"}"#,
+        )?;
+        assert_eq!(
+            request,
+            Request::AddRoute(Route {
+                endpoint: "gcs".to_string(),
+                sys_id: 1,
+                comp_id: 1,
+                address: ([127, 0, 0, 1], 14550).into(),
+            })
+        );
+        assert_eq!(
+            serde_json::from_str::<Request>(r#"{"command": "reset-stats"}"#)?,
+            Request::ResetStats { endpoint: None }
+        );
+        assert_eq!(
+            serde_json::to_string(&Response::Error("unknown".to_string()))?,
+            r#"{"error":"unknown"}"#
+        );
+        Ok(())
+    }
+}
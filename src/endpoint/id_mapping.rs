@@ -0,0 +1,192 @@
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+use super::Name;
+use crate::mavlink;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Id {
+    pub sys_id: u8,
+    /// Matches any component if not set.
+    #[serde(default)]
+    pub comp_id: Option<u8>,
+}
+
+impl Id {
+    fn matches(&self, id: mavlink::SysCompId) -> bool {
+        id.sys_id() == self.sys_id && self.comp_id.is_none_or(|c| c == id.comp_id())
+    }
+
+    fn apply(&self, id: mavlink::SysCompId) -> mavlink::SysCompId {
+        (self.sys_id, self.comp_id.unwrap_or(id.comp_id())).into()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Settings {
+    /// The ID used by the component on the endpoint's side.
+    pub internal: Id,
+    /// The ID the component appears with to all other endpoints.
+    pub external: Id,
+}
+
+/// Rewrites the IDs of components behind an endpoint, so they can be told apart from others.
+pub struct IdMapping {
+    name: Name,
+    rules: Vec<Settings>,
+    deserializer: Arc<mavlink::Deserializer>,
+}
+
+impl IdMapping {
+    pub fn new(name: Name, rules: Vec<Settings>, deserializer: Arc<mavlink::Deserializer>) -> Self {
+        Self {
+            name,
+            rules,
+            deserializer,
+        }
+    }
+
+    /// Rewrites the sender of a message received on the endpoint to its external ID.
+    /// Returns `None` if the message had to be rewritten but couldn't be.
+    pub fn ingress(&self, msg: mavlink::Message) -> Option<mavlink::Message> {
+        let sender = msg.routing_info.sender;
+        let rule = match self.rules.iter().find(|r| r.internal.matches(sender)) {
+            Some(rule) => rule,
+            None => return Some(msg),
+        };
+
+        let mapped = rule.external.apply(sender);
+        let rewritten = self.deserializer.with_sender(&msg, mapped);
+        if rewritten.is_none() {
+            warn!(
+                endpoint = &*self.name;
+                "[{}] Dropping message {} from {} which can't be mapped to {}",
+                self.name, msg.msg_id, sender, mapped
+            );
+        }
+        rewritten
+    }
+
+    /// Rewrites the target of a message sent to the endpoint to its internal ID.
+    /// Returns `None` if the message had to be rewritten but couldn't be.
+    pub fn egress(&self, msg: mavlink::Message) -> Option<mavlink::Message> {
+        let target = msg.routing_info.target;
+        if target.is_broadcast() {
+            return Some(msg);
+        }
+        let rule = match self.rules.iter().find(|r| r.external.matches(target)) {
+            Some(rule) => rule,
+            None => return Some(msg),
+        };
+
+        let mapped = rule.internal.apply(target);
+        let rewritten = self.deserializer.with_target(&msg, mapped);
+        if rewritten.is_none() {
+            warn!(
+                endpoint = &*self.name;
+                "[{}] Dropping message {} to {} which can't be mapped to {}",
+                self.name, msg.msg_id, target, mapped
+            );
+        }
+        rewritten
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mavlink::definitions::{Definitions, Offsets};
+    use std::collections::HashMap;
+
+    const COMMAND_LONG_ID: u32 = 76;
+
+    fn id_mapping() -> IdMapping {
+        let deserializer = mavlink::Deserializer::new(Definitions {
+            offsets: HashMap::from([(COMMAND_LONG_ID, Offsets::new(30, Some(31)))]),
+            crc_extras: HashMap::from([(0, 50), (COMMAND_LONG_ID, 152)]),
+        });
+        let rules = vec![Settings {
+            internal: Id {
+                sys_id: 1,
+                comp_id: None,
+            },
+            external: Id {
+                sys_id: 11,
+                comp_id: None,
+            },
+        }];
+        IdMapping::new("vehicle".into(), rules, Arc::new(deserializer))
+    }
+
+    fn heartbeat(sender: (u8, u8)) -> Arc<[u8]> {
+        let mut data = vec![0xFD, 9, 0, 0, 0, sender.0, sender.1, 0, 0, 0];
+        data.extend_from_slice(&[0; 11]);
+        data.into()
+    }
+
+    fn command_long(target: (u8, u8)) -> Arc<[u8]> {
+        let mut data = vec![0xFD, 33, 0, 0, 0, 255, 190, COMMAND_LONG_ID as u8, 0, 0];
+        let mut payload = [0; 35];
+        payload[30] = target.0;
+        payload[31] = target.1;
+        data.extend_from_slice(&payload);
+        data.into()
+    }
+
+    #[test]
+    fn test_ingress_maps_sender() -> Result<(), mavlink::DeserializationError> {
+        let id_mapping = id_mapping();
+        let msg = id_mapping.deserializer.deserialize(heartbeat((1, 1)))?;
+
+        let mapped = id_mapping.ingress(msg).unwrap();
+        assert_eq!(mapped.routing_info.sender, (11, 1).into());
+        let reparsed = id_mapping.deserializer.deserialize(mapped.data)?;
+        assert_eq!(reparsed.routing_info.sender, (11, 1).into());
+        Ok(())
+    }
+
+    #[test]
+    fn test_ingress_ignores_unmapped_senders() -> Result<(), mavlink::DeserializationError> {
+        let id_mapping = id_mapping();
+        let packet = heartbeat((2, 1));
+        let msg = id_mapping.deserializer.deserialize(packet.clone())?;
+
+        assert_eq!(id_mapping.ingress(msg).unwrap().data, packet);
+        Ok(())
+    }
+
+    #[test]
+    fn test_egress_maps_target() -> Result<(), mavlink::DeserializationError> {
+        let id_mapping = id_mapping();
+        let msg = id_mapping.deserializer.deserialize(command_long((11, 0)))?;
+
+        let mapped = id_mapping.egress(msg).unwrap();
+        assert_eq!(mapped.routing_info.target, (1, 0).into());
+        let reparsed = id_mapping.deserializer.deserialize(mapped.data)?;
+        assert_eq!(reparsed.routing_info.target, (1, 0).into());
+        Ok(())
+    }
+
+    #[test]
+    fn test_egress_ignores_broadcasts() -> Result<(), mavlink::DeserializationError> {
+        let id_mapping = id_mapping();
+        let packet = command_long((0, 0));
+        let msg = id_mapping.deserializer.deserialize(packet.clone())?;
+
+        assert_eq!(id_mapping.egress(msg).unwrap().data, packet);
+        Ok(())
+    }
+
+    #[test]
+    fn test_ingress_drops_messages_which_cant_be_mapped(
+    ) -> Result<(), mavlink::DeserializationError> {
+        let id_mapping = id_mapping();
+        let mut packet = heartbeat((1, 1)).to_vec();
+        packet[7] = 30;
+        let msg = id_mapping.deserializer.deserialize(packet.into())?;
+
+        assert!(id_mapping.ingress(msg).is_none());
+        Ok(())
+    }
+}
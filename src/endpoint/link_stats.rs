@@ -0,0 +1,379 @@
+use parking_lot::Mutex;
+use std::{
+    collections::HashMap,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+use tokio::time::Instant;
+
+use super::Name;
+use crate::mavlink;
+
+const RATE_WINDOW: Duration = Duration::from_secs(1);
+
+/// Statistics about the messages received from a single sender.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SenderStats {
+    pub received: u64,
+    /// Messages which were skipped in the sequence.
+    pub lost: u64,
+    /// Messages which were received with the same sequence number twice in a row.
+    pub duplicated: u64,
+    /// Messages which arrived after a message with a later sequence number.
+    pub out_of_order: u64,
+    pub bytes: u64,
+    pub packets_per_second: u64,
+    pub bytes_per_second: u64,
+}
+
+impl SenderStats {
+    /// The share of lost messages out of all expected messages, between 0 and 1.
+    pub fn loss_rate(&self) -> f64 {
+        let expected = self.received + self.lost;
+        if expected == 0 {
+            return 0.0;
+        }
+        self.lost as f64 / expected as f64
+    }
+}
+
+// Counts packets and bytes in fixed windows, reporting the last complete one.
+struct RateMeter {
+    window_start: Instant,
+    current: (u64, u64),
+    previous: (u64, u64),
+}
+
+impl RateMeter {
+    fn new(now: Instant) -> Self {
+        Self {
+            window_start: now,
+            current: (0, 0),
+            previous: (0, 0),
+        }
+    }
+
+    fn roll(&mut self, now: Instant) {
+        let elapsed = now.duration_since(self.window_start);
+        if elapsed < RATE_WINDOW {
+            return;
+        }
+        self.previous = if elapsed < 2 * RATE_WINDOW {
+            self.current
+        } else {
+            (0, 0)
+        };
+        self.current = (0, 0);
+        self.window_start += RATE_WINDOW * (elapsed.as_millis() / RATE_WINDOW.as_millis()) as u32;
+    }
+
+    fn record(&mut self, bytes: u64, now: Instant) {
+        self.roll(now);
+        self.current.0 += 1;
+        self.current.1 += bytes;
+    }
+
+    fn rates(&self, now: Instant) -> (u64, u64) {
+        let elapsed = now.duration_since(self.window_start);
+        if elapsed >= 2 * RATE_WINDOW {
+            (0, 0)
+        } else if elapsed >= RATE_WINDOW {
+            self.current
+        } else {
+            self.previous
+        }
+    }
+}
+
+// Sequence numbers which were skipped, so they can be told apart from others if they arrive late.
+#[derive(Default)]
+struct MissingSeqs([u64; 4]);
+
+impl MissingSeqs {
+    fn set(&mut self, seq: u8, missing: bool) {
+        let (word, bit) = (seq as usize / 64, 1 << (seq % 64));
+        if missing {
+            self.0[word] |= bit;
+        } else {
+            self.0[word] &= !bit;
+        }
+    }
+
+    fn contains(&self, seq: u8) -> bool {
+        self.0[seq as usize / 64] & (1 << (seq % 64)) != 0
+    }
+}
+
+struct SenderTracker {
+    last_seq: Option<u8>,
+    missing: MissingSeqs,
+    // An unexpected earlier sequence number, which the sender may have restarted from.
+    restarted_at: Option<u8>,
+    stats: SenderStats,
+    rate: RateMeter,
+}
+
+impl SenderTracker {
+    fn new(now: Instant) -> Self {
+        Self {
+            last_seq: None,
+            missing: MissingSeqs::default(),
+            restarted_at: None,
+            stats: SenderStats::default(),
+            rate: RateMeter::new(now),
+        }
+    }
+
+    fn record(&mut self, seq: u8, bytes: u64, now: Instant) {
+        self.stats.received += 1;
+        self.stats.bytes += bytes;
+        self.rate.record(bytes, now);
+
+        let last_seq = match self.last_seq {
+            Some(last_seq) => last_seq,
+            None => {
+                self.last_seq = Some(seq);
+                return;
+            }
+        };
+
+        // Sequence numbers wrap around, so a difference of up to half the range counts as ahead.
+        match seq.wrapping_sub(last_seq) {
+            0 => self.stats.duplicated += 1,
+            diff @ 1..=128 => {
+                self.stats.lost += diff as u64 - 1;
+                for skipped in 1..diff {
+                    self.missing.set(last_seq.wrapping_add(skipped), true);
+                }
+                self.missing.set(seq, false);
+                self.last_seq = Some(seq);
+                self.restarted_at = None;
+            }
+            _ if self.missing.contains(seq) => {
+                // The message was counted as lost when the gap was first seen.
+                self.stats.out_of_order += 1;
+                self.stats.lost -= 1;
+                self.missing.set(seq, false);
+            }
+            _ if self.restarted_at == Some(seq.wrapping_sub(1)) => {
+                // The sender restarted its sequence, or the link dropped out for longer than
+                // half the sequence range.
+                self.missing = MissingSeqs::default();
+                self.last_seq = Some(seq);
+                self.restarted_at = None;
+            }
+            _ => self.restarted_at = Some(seq),
+        }
+    }
+
+    fn snapshot(&self, now: Instant) -> SenderStats {
+        let (packets_per_second, bytes_per_second) = self.rate.rates(now);
+        SenderStats {
+            packets_per_second,
+            bytes_per_second,
+            ..self.stats.clone()
+        }
+    }
+}
+
+/// Tracks the link quality of every sender seen on an endpoint.
+pub struct LinkStats {
+    name: Name,
+    senders: Mutex<HashMap<mavlink::SysCompId, SenderTracker>>,
+    invalid_senders: AtomicU64,
+}
+
+impl LinkStats {
+    pub fn new(name: Name) -> Self {
+        Self {
+            name,
+            senders: Mutex::new(HashMap::new()),
+            invalid_senders: AtomicU64::new(0),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn record(&self, msg: &mavlink::Message) {
+        let now = Instant::now();
+        self.senders
+            .lock()
+            .entry(msg.routing_info.sender)
+            .or_insert_with(|| SenderTracker::new(now))
+            .record(msg.seq, msg.data.len() as u64, now);
+    }
+
+    pub fn record_invalid_sender(&self) {
+        self.invalid_senders.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// The number of messages received with an invalid sender ID.
+    pub fn invalid_senders(&self) -> u64 {
+        self.invalid_senders.load(Ordering::Relaxed)
+    }
+
+    /// The statistics of all senders, ordered by their ID.
+    pub fn snapshot(&self) -> Vec<(mavlink::SysCompId, SenderStats)> {
+        let now = Instant::now();
+        let mut snapshot: Vec<_> = self
+            .senders
+            .lock()
+            .iter()
+            .map(|(sender, tracker)| (*sender, tracker.snapshot(now)))
+            .collect();
+        snapshot.sort_by_key(|(sender, _)| (sender.sys_id(), sender.comp_id()));
+        snapshot
+    }
+
+    pub fn reset(&self) {
+        self.senders.lock().clear();
+        self.invalid_senders.store(0, Ordering::Relaxed);
+    }
+
+    pub fn log(&self) {
+        for (sender, stats) in self.snapshot() {
+            log::info!(
+                endpoint = &*self.name;
+                "[{}] {}: received {}, lost {} ({:.1}%), duplicated {}, out of order {}, {} packets/s, {} B/s",
+                self.name,
+                sender,
+                stats.received,
+                stats.lost,
+                stats.loss_rate() * 100.0,
+                stats.duplicated,
+                stats.out_of_order,
+                stats.packets_per_second,
+                stats.bytes_per_second
+            );
+        }
+        let invalid_senders = self.invalid_senders();
+        if invalid_senders > 0 {
+            log::info!(
+                endpoint = &*self.name;
+                "[{}] received {} messages with invalid sender id",
+                self.name,
+                invalid_senders
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record_all(tracker: &mut SenderTracker, seqs: &[u8], now: Instant) {
+        for &seq in seqs {
+            tracker.record(seq, 10, now);
+        }
+    }
+
+    #[test]
+    fn test_record_in_sequence() {
+        let now = Instant::now();
+        let mut tracker = SenderTracker::new(now);
+        record_all(&mut tracker, &[1, 2, 3, 4], now);
+
+        assert_eq!(tracker.stats.received, 4);
+        assert_eq!(tracker.stats.bytes, 40);
+        assert_eq!(tracker.stats.lost, 0);
+        assert_eq!(tracker.stats.duplicated, 0);
+        assert_eq!(tracker.stats.out_of_order, 0);
+    }
+
+    #[test]
+    fn test_record_counts_gaps_as_lost() {
+        let now = Instant::now();
+        let mut tracker = SenderTracker::new(now);
+        record_all(&mut tracker, &[1, 2, 5, 6], now);
+
+        assert_eq!(tracker.stats.lost, 2);
+        assert_eq!(tracker.stats.loss_rate(), 2.0 / 6.0);
+    }
+
+    #[test]
+    fn test_record_handles_wrap_around() {
+        let now = Instant::now();
+        let mut tracker = SenderTracker::new(now);
+        record_all(&mut tracker, &[254, 255, 0, 2], now);
+
+        assert_eq!(tracker.stats.lost, 1);
+        assert_eq!(tracker.stats.out_of_order, 0);
+    }
+
+    #[test]
+    fn test_record_duplicates() {
+        let now = Instant::now();
+        let mut tracker = SenderTracker::new(now);
+        record_all(&mut tracker, &[1, 2, 2, 3], now);
+
+        assert_eq!(tracker.stats.duplicated, 1);
+        assert_eq!(tracker.stats.lost, 0);
+    }
+
+    #[test]
+    fn test_record_out_of_order() {
+        let now = Instant::now();
+        let mut tracker = SenderTracker::new(now);
+        record_all(&mut tracker, &[1, 3, 2, 4], now);
+
+        assert_eq!(tracker.stats.out_of_order, 1);
+        assert_eq!(tracker.stats.lost, 0);
+    }
+
+    #[test]
+    fn test_record_late_message_only_once() {
+        let now = Instant::now();
+        let mut tracker = SenderTracker::new(now);
+        record_all(&mut tracker, &[1, 3, 2, 2, 4], now);
+
+        assert_eq!(tracker.stats.out_of_order, 1);
+        assert_eq!(tracker.stats.lost, 0);
+    }
+
+    #[test]
+    fn test_record_resyncs_after_long_dropout() {
+        let now = Instant::now();
+        let mut tracker = SenderTracker::new(now);
+        record_all(&mut tracker, &[0, 1, 2], now);
+        // More than half the sequence range was lost, which looks like the sender went back.
+        record_all(&mut tracker, &[200, 201, 202, 204, 205], now);
+
+        assert_eq!(tracker.stats.received, 8);
+        assert_eq!(tracker.stats.out_of_order, 0);
+        assert_eq!(tracker.stats.lost, 1);
+    }
+
+    #[test]
+    fn test_record_resyncs_after_restart() {
+        let now = Instant::now();
+        let mut tracker = SenderTracker::new(now);
+        record_all(&mut tracker, &[98, 99, 100, 0, 1, 2, 4], now);
+
+        assert_eq!(tracker.stats.out_of_order, 0);
+        assert_eq!(tracker.stats.lost, 1);
+    }
+
+    #[test]
+    fn test_rates_report_last_complete_window() {
+        let now = Instant::now();
+        let mut tracker = SenderTracker::new(now);
+        record_all(&mut tracker, &[1, 2, 3], now);
+
+        let snapshot = tracker.snapshot(now);
+        assert_eq!(snapshot.packets_per_second, 0);
+
+        let snapshot = tracker.snapshot(now + Duration::from_millis(1500));
+        assert_eq!(snapshot.packets_per_second, 3);
+        assert_eq!(snapshot.bytes_per_second, 30);
+
+        record_all(&mut tracker, &[4], now + Duration::from_millis(1500));
+        let snapshot = tracker.snapshot(now + Duration::from_millis(1500));
+        assert_eq!(snapshot.packets_per_second, 3);
+
+        let snapshot = tracker.snapshot(now + Duration::from_millis(3100));
+        assert_eq!(snapshot.packets_per_second, 0);
+    }
+}
@@ -0,0 +1,106 @@
+use log::{debug, info};
+use serde::{Deserialize, Serialize};
+use std::net::{Ipv4Addr, SocketAddr, UdpSocket};
+
+use super::Name;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Settings {
+    /// Where to send the copies of the endpoint's traffic.
+    pub address: SocketAddr,
+    /// The local address copies of received traffic are sent from, any free port if not set.
+    #[serde(default)]
+    pub received_from: Option<SocketAddr>,
+    /// The local address copies of sent traffic are sent from, any free port if not set.
+    #[serde(default)]
+    pub sent_from: Option<SocketAddr>,
+}
+
+fn bind(addr: Option<SocketAddr>) -> std::io::Result<UdpSocket> {
+    let addr = addr.unwrap_or((Ipv4Addr::UNSPECIFIED, 0).into());
+    let socket = UdpSocket::bind(addr)?;
+    socket.set_nonblocking(true)?;
+    Ok(socket)
+}
+
+/// Copies the raw traffic of an endpoint to a UDP address, telling the directions apart by the
+/// source port of the copies.
+pub struct Mirror {
+    name: Name,
+    address: SocketAddr,
+    received: UdpSocket,
+    sent: UdpSocket,
+}
+
+impl Mirror {
+    pub fn new(name: Name, settings: &Settings) -> std::io::Result<Self> {
+        let received = bind(settings.received_from)?;
+        let sent = bind(settings.sent_from)?;
+        info!(
+            endpoint = &*name;
+            "[{}] Mirroring traffic to {}, received from {} and sent from {}",
+            name,
+            settings.address,
+            received.local_addr()?,
+            sent.local_addr()?
+        );
+        Ok(Self {
+            name,
+            address: settings.address,
+            received,
+            sent,
+        })
+    }
+
+    pub fn received(&self, data: &[u8]) {
+        self.mirror(&self.received, data);
+    }
+
+    /// Sent packets are mirrored once, regardless of the number of peers they went to.
+    pub fn sent(&self, data: &[u8]) {
+        self.mirror(&self.sent, data);
+    }
+
+    // Never waits, so a slow consumer of the copies can't affect routing.
+    fn mirror(&self, socket: &UdpSocket, data: &[u8]) {
+        if let Err(e) = socket.send_to(data, self.address) {
+            debug!(
+                endpoint = &*self.name;
+                "[{}] Failed to mirror packet: {}", self.name, e
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_directions_use_different_sources() -> std::io::Result<()> {
+        let consumer = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0))?;
+        consumer.set_read_timeout(Some(std::time::Duration::from_secs(1)))?;
+        let mirror = Mirror::new(
+            "test".into(),
+            &Settings {
+                address: consumer.local_addr()?,
+                received_from: Some((Ipv4Addr::LOCALHOST, 0).into()),
+                sent_from: Some((Ipv4Addr::LOCALHOST, 0).into()),
+            },
+        )?;
+
+        let mut buf = [0; 16];
+        mirror.received(&[1, 2]);
+        let (len, received_from) = consumer.recv_from(&mut buf)?;
+        assert_eq!(&buf[..len], &[1, 2]);
+
+        mirror.sent(&[3]);
+        let (len, sent_from) = consumer.recv_from(&mut buf)?;
+        assert_eq!(&buf[..len], &[3]);
+
+        assert_eq!(received_from, mirror.received.local_addr()?);
+        assert_eq!(sent_from, mirror.sent.local_addr()?);
+        assert_ne!(received_from, sent_from);
+        Ok(())
+    }
+}
@@ -1,82 +1,349 @@
 use serde::{Deserialize, Serialize};
-use std::sync::Arc;
-use tokio::sync::mpsc;
+use std::sync::{atomic::AtomicU64, Arc};
 
+pub use link_stats::{LinkStats, SenderStats};
+pub use queue::{EndpointTx, QueueError};
+
+use id_mapping::IdMapping;
+use mirror::Mirror;
+use priority::Priorities;
 use receiver::Receiver;
 use sender::Sender;
-use target_database::TargetDatabase;
+use target_database::{IdLock, TargetDatabase};
 use transmitter::*;
 
-use crate::{mavlink, router};
+use crate::{mavlink, metrics::EndpointMetrics, router};
 
+pub mod id_mapping;
+mod link_stats;
+pub mod mirror;
+pub mod priority;
+mod queue;
+pub mod rate_limiter;
 mod receiver;
 mod sender;
-mod target_database;
+pub mod shaper;
+pub mod target_database;
 pub mod transmitter;
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct EndpointSettings {
     pub name: String,
     pub kind: transmitter::Settings,
+    /// Output rate caps for messages sent to the endpoint.
+    #[serde(default)]
+    pub rate_limits: Vec<rate_limiter::Settings>,
+    /// Priorities of messages queued for the endpoint, in addition to the defaults.
+    #[serde(default)]
+    pub priorities: Vec<priority::Settings>,
+    /// Limits the bandwidth used when sending to the endpoint.
+    #[serde(default)]
+    pub bandwidth: Option<shaper::Settings>,
+    /// Rewrites the IDs of components behind the endpoint.
+    #[serde(default)]
+    pub id_mapping: Vec<id_mapping::Settings>,
+    /// How the addresses of components behind the endpoint are learned.
+    #[serde(default)]
+    pub route_learning: target_database::Settings,
+    /// What to do with messages from senders using the reserved ID 0.
+    #[serde(default)]
+    pub invalid_senders: receiver::InvalidSenderPolicy,
+    /// The routing domains of the endpoint, messages only reach endpoints sharing a group.
+    #[serde(default)]
+    pub groups: Vec<String>,
+    /// Sends every routed message to all peers of the endpoint, regardless of its target.
+    #[serde(default)]
+    pub sniffer: bool,
+    /// Copies everything the endpoint receives and sends to a UDP address.
+    #[serde(default)]
+    pub mirror: Option<mirror::Settings>,
+}
+
+impl EndpointSettings {
+    /// Settings for an endpoint without any of the optional features.
+    pub fn new(name: impl Into<String>, kind: transmitter::Settings) -> Self {
+        Self {
+            name: name.into(),
+            kind,
+            rate_limits: Vec::new(),
+            priorities: Vec::new(),
+            bandwidth: None,
+            id_mapping: Vec::new(),
+            route_learning: Default::default(),
+            invalid_senders: Default::default(),
+            groups: Vec::new(),
+            sniffer: false,
+            mirror: None,
+        }
+    }
+}
+
+/// Parses `name=url` or just `url`, which is then also used as the name.
+impl std::str::FromStr for EndpointSettings {
+    type Err = transmitter::ParseError;
+
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        // URLs can contain '=' in their options, but names can't contain ':'
+        match spec.split_once('=') {
+            Some((name, url)) if !name.contains(':') => Ok(Self::new(name, url.parse()?)),
+            _ => Ok(Self::new(spec, spec.parse()?)),
+        }
+    }
 }
 
-type Name = Arc<str>;
+pub type Name = Arc<str>;
 
 pub struct Endpoint {
+    name: Name,
     sender: Sender,
     receiver: Receiver,
+    link_stats: Arc<LinkStats>,
+    metrics: Arc<EndpointMetrics>,
+    targets: Arc<TargetDatabase>,
+    transmitter_tasks: Tasks,
+}
+
+/// An endpoint whose tasks have been started.
+pub struct RunningEndpoint {
+    name: Name,
+    tasks: Tasks,
+}
+
+impl RunningEndpoint {
+    pub fn name(&self) -> &Name {
+        &self.name
+    }
+
+    /// Stops all tasks of the endpoint, which closes its sockets before returning.
+    pub async fn stop(self) {
+        self.tasks.abort();
+        for task in self.tasks.receiving.into_iter().chain(self.tasks.sending) {
+            // The tasks have been cancelled, so there is nothing to report
+            let _ = task.await;
+        }
+    }
+
+    /// Stops receiving, so the endpoint doesn't pass any more messages to the router.
+    pub async fn stop_receiving(&mut self) {
+        for task in self.tasks.receiving.drain(..) {
+            task.abort();
+            let _ = task.await;
+        }
+    }
+
+    /// Waits until everything queued for the endpoint has been sent, which requires the router
+    /// to have released the endpoint's queue.
+    pub async fn drained(&mut self) {
+        // Finished tasks are removed right away, since they can't be awaited twice
+        while let Some(task) = self.tasks.sending.last_mut() {
+            let _ = task.await;
+            self.tasks.sending.pop();
+        }
+    }
 }
 
 impl Endpoint {
     pub fn new(
-        name: String,
+        settings: &EndpointSettings,
         transmitter: Transmitter,
         routing_channel: router::RouterTx,
         deserializer: Arc<mavlink::Deserializer>,
-    ) -> (mpsc::Sender<mavlink::Message>, Self) {
-        let name: Name = name.into();
-        let (transmitter_tx, transmitter_rx) = transmitter.split();
-        let discovered_targets = Arc::new(TargetDatabase::new());
+        id_locks: &[IdLock],
+        mirror: Option<Arc<Mirror>>,
+        failed_sends: Arc<AtomicU64>,
+    ) -> (EndpointTx, Self) {
+        let name: Name = settings.name.as_str().into();
+        let (transmitter_tx, transmitter_rx, transmitter_tasks) = transmitter.split();
+        let discovered_targets = Arc::new(TargetDatabase::new(
+            name.clone(),
+            settings.route_learning.clone(),
+            id_locks,
+        ));
+        let link_stats = Arc::new(LinkStats::new(name.clone()));
+        let metrics = Arc::new(EndpointMetrics::new(
+            name.clone(),
+            discovered_targets.clone(),
+            failed_sends,
+        ));
+        let id_mapping = Arc::new(IdMapping::new(
+            name.clone(),
+            settings.id_mapping.clone(),
+            deserializer.clone(),
+        ));
 
-        // Create a channel for sending messages to the endpoint
-        let (tx, rx) = mpsc::channel(16);
+        // Create a priority queue for sending messages to the endpoint
+        let (tx, rx) = queue::channel(16, Priorities::new(&settings.priorities), metrics.clone());
 
-        let sender = Sender::new(name.clone(), transmitter_tx, discovered_targets.clone(), rx);
+        let sender = Sender::new(
+            name.clone(),
+            transmitter_tx,
+            discovered_targets.clone(),
+            rx,
+            settings,
+            id_mapping.clone(),
+            mirror.clone(),
+            metrics.clone(),
+        );
         let receiver = Receiver::new(
-            name,
+            name.clone(),
             transmitter_rx,
-            discovered_targets,
+            discovered_targets.clone(),
             routing_channel,
             deserializer,
+            link_stats.clone(),
+            id_mapping,
+            settings.invalid_senders,
+            mirror,
+            metrics.clone(),
         );
-        (tx, Self { sender, receiver })
+        (
+            tx,
+            Self {
+                name,
+                sender,
+                receiver,
+                link_stats,
+                metrics,
+                targets: discovered_targets,
+                transmitter_tasks,
+            },
+        )
+    }
+
+    pub fn name(&self) -> &Name {
+        &self.name
+    }
+
+    pub fn link_stats(&self) -> &Arc<LinkStats> {
+        &self.link_stats
+    }
+
+    pub fn metrics(&self) -> &Arc<EndpointMetrics> {
+        &self.metrics
+    }
+
+    pub fn targets(&self) -> &Arc<TargetDatabase> {
+        &self.targets
     }
 
     pub fn from_settings(
         settings: EndpointSettings,
-        routing_channel: mpsc::Sender<mavlink::Message>,
+        routing_channel: router::RouterTx,
         deserializer: Arc<mavlink::Deserializer>,
-    ) -> Result<(mpsc::Sender<mavlink::Message>, Self), std::io::Error> {
-        let transmitter = Transmitter::new(settings.kind)?;
+        id_locks: &[IdLock],
+    ) -> Result<(EndpointTx, Self), std::io::Error> {
+        let failed_sends = Arc::new(AtomicU64::new(0));
+        let transmitter = Transmitter::new(
+            settings.name.as_str().into(),
+            settings.kind.clone(),
+            deserializer.clone(),
+            failed_sends.clone(),
+        )?;
+        let mirror = settings
+            .mirror
+            .as_ref()
+            .map(|mirror| Mirror::new(settings.name.as_str().into(), mirror))
+            .transpose()?
+            .map(Arc::new);
         Ok(Self::new(
-            settings.name,
+            &settings,
             transmitter,
             routing_channel,
             deserializer,
+            id_locks,
+            mirror,
+            failed_sends,
         ))
     }
 
-    pub fn start(self) {
+    pub fn start(self) -> RunningEndpoint {
+        let mut tasks = self.transmitter_tasks;
+
         // Start sending messages received from the router
         let mut sender = self.sender;
-        tokio::spawn(async move {
+        tasks.sending.push(tokio::spawn(async move {
             sender.run().await;
-        });
+        }));
 
         // Start receiving messages from the endpoint
         let mut receiver = self.receiver;
-        tokio::spawn(async move {
+        tasks.receiving.push(tokio::spawn(async move {
             receiver.run().await;
-        });
+        }));
+
+        RunningEndpoint {
+            name: self.name,
+            tasks,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{Ipv4Addr, SocketAddr};
+    use tokio::sync::mpsc;
+
+    #[test]
+    fn test_parse_endpoint_spec() -> Result<(), transmitter::ParseError> {
+        let named: EndpointSettings = "gcs=udp://0.0.0.0:14550".parse()?;
+        assert_eq!(named.name, "gcs");
+        assert_eq!(named.kind.to_string(), "udp://0.0.0.0:14550");
+
+        let unnamed: EndpointSettings = "tcp://:5760".parse()?;
+        assert_eq!(unnamed.name, "tcp://:5760");
+        assert_eq!(unnamed.kind.to_string(), "tcp://0.0.0.0:5760");
+
+        let serial: EndpointSettings = "fc=serial:///dev/ttyACM0?baud=921600".parse()?;
+        assert_eq!(serial.name, "fc");
+        assert_eq!(serial.kind.to_string(), "serial:///dev/ttyACM0?baud=921600");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_stop_releases_socket() -> Result<(), std::io::Error> {
+        let address: SocketAddr =
+            std::net::UdpSocket::bind((Ipv4Addr::LOCALHOST, 0))?.local_addr()?;
+        let settings =
+            EndpointSettings::new("udp", transmitter::Settings::Udp(udp::Settings { address }));
+        let (router_tx, _router_rx) = mpsc::channel(1);
+        let deserializer = Arc::new(mavlink::Deserializer::new(Default::default()));
+
+        let (_tx, endpoint) = Endpoint::from_settings(settings, router_tx, deserializer, &[])?;
+        let running = endpoint.start();
+        assert!(std::net::UdpSocket::bind(address).is_err());
+
+        running.stop().await;
+        std::net::UdpSocket::bind(address)?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_drained_closes_tcp_connections() -> Result<(), std::io::Error> {
+        use tokio::io::AsyncReadExt;
+
+        let address: SocketAddr =
+            std::net::TcpListener::bind((Ipv4Addr::LOCALHOST, 0))?.local_addr()?;
+        let settings =
+            EndpointSettings::new("tcp", transmitter::Settings::Tcp(tcp::Settings { address }));
+        let (router_tx, _router_rx) = mpsc::channel(1);
+        let deserializer = Arc::new(mavlink::Deserializer::new(Default::default()));
+
+        let (tx, endpoint) = Endpoint::from_settings(settings, router_tx, deserializer, &[])?;
+        let mut running = endpoint.start();
+        let mut client = tokio::net::TcpStream::connect(address).await?;
+        // Give the endpoint time to accept the connection
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+
+        running.stop_receiving().await;
+        drop(tx);
+        tokio::time::timeout(std::time::Duration::from_secs(1), running.drained())
+            .await
+            .expect("the endpoint should drain");
+
+        let mut buf = [0; 1];
+        assert_eq!(client.read(&mut buf).await?, 0);
+        running.stop().await;
+        Ok(())
     }
 }
@@ -0,0 +1,98 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Priority {
+    High,
+    Normal,
+    Low,
+}
+
+impl Priority {
+    pub fn index(self) -> usize {
+        self as usize
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Settings {
+    /// The ID of the message to assign the priority to.
+    pub message_id: u32,
+    pub priority: Priority,
+}
+
+// Commands, manual control and heartbeats are what keeps a vehicle controllable.
+const DEFAULT_HIGH: [u32; 6] = [
+    0,  // HEARTBEAT
+    69, // MANUAL_CONTROL
+    75, // COMMAND_INT
+    76, // COMMAND_LONG
+    77, // COMMAND_ACK
+    80, // COMMAND_CANCEL
+];
+
+// Bulk transfers, which can happily wait for a free link.
+const DEFAULT_LOW: [u32; 3] = [
+    110, // FILE_TRANSFER_PROTOCOL
+    120, // LOG_DATA
+    131, // ENCAPSULATED_DATA
+];
+
+/// Maps message IDs to their priority, everything not listed is of normal priority.
+#[derive(Debug, Clone)]
+pub struct Priorities {
+    priorities: HashMap<u32, Priority>,
+}
+
+impl Priorities {
+    pub fn new(settings: &[Settings]) -> Self {
+        let defaults = DEFAULT_HIGH
+            .iter()
+            .map(|&id| (id, Priority::High))
+            .chain(DEFAULT_LOW.iter().map(|&id| (id, Priority::Low)));
+        let configured = settings.iter().map(|s| (s.message_id, s.priority));
+
+        Self {
+            priorities: defaults.chain(configured).collect(),
+        }
+    }
+
+    pub fn get(&self, msg_id: u32) -> Priority {
+        self.priorities
+            .get(&msg_id)
+            .copied()
+            .unwrap_or(Priority::Normal)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_defaults() {
+        let priorities = Priorities::new(&[]);
+        assert_eq!(priorities.get(0), Priority::High);
+        assert_eq!(priorities.get(76), Priority::High);
+        assert_eq!(priorities.get(30), Priority::Normal);
+        assert_eq!(priorities.get(120), Priority::Low);
+    }
+
+    #[test]
+    fn test_settings_override_defaults() {
+        let priorities = Priorities::new(&[
+            Settings {
+                message_id: 120,
+                priority: Priority::Normal,
+            },
+            Settings {
+                message_id: 30,
+                priority: Priority::Low,
+            },
+        ]);
+        assert_eq!(priorities.get(120), Priority::Normal);
+        assert_eq!(priorities.get(30), Priority::Low);
+        assert_eq!(priorities.get(0), Priority::High);
+    }
+}
@@ -0,0 +1,209 @@
+use std::sync::Arc;
+use tokio::sync::mpsc;
+
+use super::priority::{Priorities, Priority};
+use crate::{
+    mavlink,
+    metrics::{DropReason, EndpointMetrics},
+};
+
+#[derive(Debug, PartialEq, thiserror::Error)]
+pub enum QueueError {
+    #[error("The queue is full")]
+    Full,
+    #[error("The queue is closed")]
+    Closed,
+}
+
+/// Creates a queue with one channel per priority, each holding up to `capacity` messages. Its
+/// length and the messages it drops are tracked in `metrics`.
+pub fn channel(
+    capacity: usize,
+    priorities: Priorities,
+    metrics: Arc<EndpointMetrics>,
+) -> (EndpointTx, EndpointRx) {
+    let (high_tx, high_rx) = mpsc::channel(capacity);
+    let (normal_tx, normal_rx) = mpsc::channel(capacity);
+    let (low_tx, low_rx) = mpsc::channel(capacity);
+
+    let tx = EndpointTx {
+        priorities,
+        channels: [high_tx, normal_tx, low_tx],
+        metrics: metrics.clone(),
+    };
+    let rx = EndpointRx {
+        high: high_rx,
+        normal: normal_rx,
+        low: low_rx,
+        metrics,
+    };
+    (tx, rx)
+}
+
+/// The sending half of an endpoint queue, which sorts messages by their priority.
+#[derive(Clone)]
+pub struct EndpointTx {
+    priorities: Priorities,
+    channels: [mpsc::Sender<mavlink::Message>; 3],
+    metrics: Arc<EndpointMetrics>,
+}
+
+impl EndpointTx {
+    pub async fn send(
+        &self,
+        msg: mavlink::Message,
+    ) -> Result<(), mpsc::error::SendError<mavlink::Message>> {
+        let priority = self.priorities.get(msg.msg_id);
+        // Counted up front, since the message may be received before sending returns
+        self.metrics.enqueued();
+        let res = self.channels[priority.index()].send(msg).await;
+        if res.is_err() {
+            self.metrics.dequeued();
+        }
+        res
+    }
+
+    /// Queues a message without waiting for room, unless it is of high priority. Other messages
+    /// are dropped when their queue is full, so a slow endpoint can't hold up the router.
+    pub async fn forward(&self, msg: mavlink::Message) -> Result<(), QueueError> {
+        let priority = self.priorities.get(msg.msg_id);
+        if priority == Priority::High {
+            return self.send(msg).await.map_err(|_| QueueError::Closed);
+        }
+        self.metrics.enqueued();
+        self.channels[priority.index()].try_send(msg).map_err(|e| {
+            self.metrics.dequeued();
+            match e {
+                mpsc::error::TrySendError::Full(_) => {
+                    self.metrics.dropped(DropReason::QueueFull, 1);
+                    QueueError::Full
+                }
+                mpsc::error::TrySendError::Closed(_) => QueueError::Closed,
+            }
+        })
+    }
+}
+
+/// The receiving half of an endpoint queue, which always yields the highest priority message first.
+pub struct EndpointRx {
+    high: mpsc::Receiver<mavlink::Message>,
+    normal: mpsc::Receiver<mavlink::Message>,
+    low: mpsc::Receiver<mavlink::Message>,
+    metrics: Arc<EndpointMetrics>,
+}
+
+impl EndpointRx {
+    pub async fn recv(&mut self) -> Option<mavlink::Message> {
+        let msg = tokio::select! {
+            biased;
+            Some(msg) = self.high.recv() => Some(msg),
+            Some(msg) = self.normal.recv() => Some(msg),
+            Some(msg) = self.low.recv() => Some(msg),
+            else => None,
+        };
+        if msg.is_some() {
+            self.metrics.dequeued();
+        }
+        msg
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::endpoint::{priority, target_database::TargetDatabase};
+
+    fn metrics() -> Arc<EndpointMetrics> {
+        let targets = TargetDatabase::new("test".into(), Default::default(), &[]);
+        Arc::new(EndpointMetrics::new(
+            "test".into(),
+            Arc::new(targets),
+            Default::default(),
+        ))
+    }
+
+    fn message(msg_id: u32) -> mavlink::Message {
+        mavlink::Message {
+            routing_info: mavlink::RoutingInfo {
+                sender: (1, 1).into(),
+                target: (0, 0).into(),
+            },
+            msg_id,
+            seq: 0,
+            checksum: 0,
+            data: [msg_id as u8].into(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_recv_yields_highest_priority_first() {
+        let (tx, mut rx) = channel(4, Priorities::new(&[]), metrics());
+
+        tx.send(message(120)).await.unwrap();
+        tx.send(message(30)).await.unwrap();
+        tx.send(message(31)).await.unwrap();
+        tx.send(message(76)).await.unwrap();
+
+        let mut ids = Vec::new();
+        for _ in 0..4 {
+            ids.push(rx.recv().await.unwrap().msg_id);
+        }
+        assert_eq!(ids, vec![76, 30, 31, 120]);
+    }
+
+    #[tokio::test]
+    async fn test_recv_uses_configured_priorities() {
+        let priorities = Priorities::new(&[priority::Settings {
+            message_id: 30,
+            priority: Priority::High,
+        }]);
+        let (tx, mut rx) = channel(4, priorities, metrics());
+
+        tx.send(message(31)).await.unwrap();
+        tx.send(message(30)).await.unwrap();
+
+        assert_eq!(rx.recv().await.unwrap().msg_id, 30);
+        assert_eq!(rx.recv().await.unwrap().msg_id, 31);
+    }
+
+    #[tokio::test]
+    async fn test_recv_drains_before_closing() {
+        let (tx, mut rx) = channel(4, Priorities::new(&[]), metrics());
+
+        tx.send(message(120)).await.unwrap();
+        drop(tx);
+
+        assert_eq!(rx.recv().await.unwrap().msg_id, 120);
+        assert!(rx.recv().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_full_priority_does_not_block_others() {
+        let (tx, mut rx) = channel(1, Priorities::new(&[]), metrics());
+
+        tx.send(message(30)).await.unwrap();
+        assert!(tx.channels[Priority::Normal.index()]
+            .try_send(message(31))
+            .is_err());
+        tx.send(message(76)).await.unwrap();
+
+        assert_eq!(rx.recv().await.unwrap().msg_id, 76);
+        assert_eq!(rx.recv().await.unwrap().msg_id, 30);
+    }
+
+    #[tokio::test]
+    async fn test_forward_drops_only_below_high_priority() {
+        let (tx, mut rx) = channel(1, Priorities::new(&[]), metrics());
+
+        assert_eq!(tx.forward(message(30)).await, Ok(()));
+        assert_eq!(tx.forward(message(31)).await, Err(QueueError::Full));
+        assert_eq!(tx.forward(message(76)).await, Ok(()));
+
+        // A full high priority queue makes the router wait instead
+        let blocked = tokio::spawn(async move { tx.forward(message(77)).await });
+        assert_eq!(rx.recv().await.unwrap().msg_id, 76);
+        assert_eq!(blocked.await.unwrap(), Ok(()));
+        assert_eq!(rx.recv().await.unwrap().msg_id, 77);
+        assert_eq!(rx.recv().await.unwrap().msg_id, 30);
+    }
+}
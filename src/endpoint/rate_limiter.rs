@@ -0,0 +1,260 @@
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, time::Duration};
+use tokio::time::Instant;
+
+use crate::mavlink;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Settings {
+    /// The ID of the message to limit.
+    pub message_id: u32,
+    /// The maximum rate in Hz at which the message is sent to the endpoint.
+    pub max_rate: f64,
+    /// Whether each source component gets its own rate cap.
+    #[serde(default)]
+    pub per_component: bool,
+}
+
+type Key = (u32, Option<mavlink::SysCompId>);
+
+struct Limit {
+    interval: Duration,
+    per_component: bool,
+}
+
+struct Slot {
+    interval: Duration,
+    last_sent: Instant,
+    // The latest message that arrived too early to be sent.
+    pending: Option<mavlink::Message>,
+}
+
+impl Slot {
+    fn deadline(&self) -> Instant {
+        self.last_sent + self.interval
+    }
+}
+
+/// Downsamples messages to a maximum rate, keeping the latest message of each interval.
+pub struct RateLimiter {
+    limits: HashMap<u32, Limit>,
+    slots: HashMap<Key, Slot>,
+    // Pending messages which were replaced by newer ones before being sent.
+    superseded: usize,
+}
+
+impl RateLimiter {
+    pub fn new(settings: &[Settings]) -> Self {
+        let limits = settings
+            .iter()
+            .filter_map(|s| match Duration::try_from_secs_f64(s.max_rate.recip()) {
+                Ok(interval) => Some((
+                    s.message_id,
+                    Limit {
+                        interval,
+                        per_component: s.per_component,
+                    },
+                )),
+                Err(_) => {
+                    warn!(
+                        "Ignoring rate limit for message {} with invalid rate: {}",
+                        s.message_id, s.max_rate
+                    );
+                    None
+                }
+            })
+            .collect();
+
+        Self {
+            limits,
+            slots: HashMap::new(),
+            superseded: 0,
+        }
+    }
+
+    /// Returns the message if it may be sent right away, otherwise keeps it until its slot is due.
+    pub fn admit(&mut self, msg: mavlink::Message, now: Instant) -> Option<mavlink::Message> {
+        let limit = match self.limits.get(&msg.msg_id) {
+            Some(limit) => limit,
+            None => return Some(msg),
+        };
+
+        let sender = limit.per_component.then_some(msg.routing_info.sender);
+        match self.slots.get_mut(&(msg.msg_id, sender)) {
+            Some(slot) if now < slot.deadline() => {
+                if slot.pending.replace(msg).is_some() {
+                    self.superseded += 1;
+                }
+                None
+            }
+            Some(slot) => {
+                slot.last_sent = now;
+                slot.pending = None;
+                Some(msg)
+            }
+            None => {
+                let slot = Slot {
+                    interval: limit.interval,
+                    last_sent: now,
+                    pending: None,
+                };
+                self.slots.insert((msg.msg_id, sender), slot);
+                Some(msg)
+            }
+        }
+    }
+
+    /// The earliest point in time at which a pending message becomes due.
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.slots
+            .values()
+            .filter(|slot| slot.pending.is_some())
+            .map(Slot::deadline)
+            .min()
+    }
+
+    /// Takes all pending messages whose slot is due.
+    pub fn take_due(&mut self, now: Instant) -> Vec<mavlink::Message> {
+        self.slots
+            .values_mut()
+            .filter(|slot| slot.pending.is_some() && slot.deadline() <= now)
+            .filter_map(|slot| {
+                slot.last_sent = now;
+                slot.pending.take()
+            })
+            .collect()
+    }
+
+    /// The number of messages held back until their slot is due.
+    pub fn pending(&self) -> usize {
+        self.slots.values().filter(|s| s.pending.is_some()).count()
+    }
+
+    /// Returns the number of messages replaced by newer ones since the last call.
+    pub fn take_superseded(&mut self) -> usize {
+        std::mem::take(&mut self.superseded)
+    }
+
+    /// Takes all pending messages, whether their slot is due or not.
+    pub fn take_all(&mut self) -> Vec<mavlink::Message> {
+        self.slots
+            .values_mut()
+            .filter_map(|slot| slot.pending.take())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(msg_id: u32, sender: (u8, u8), data: &[u8]) -> mavlink::Message {
+        mavlink::Message {
+            routing_info: mavlink::RoutingInfo {
+                sender: sender.into(),
+                target: (0, 0).into(),
+            },
+            msg_id,
+            seq: 0,
+            checksum: 0,
+            data: data.into(),
+        }
+    }
+
+    fn limiter(per_component: bool) -> RateLimiter {
+        RateLimiter::new(&[Settings {
+            message_id: 30,
+            max_rate: 10.0,
+            per_component,
+        }])
+    }
+
+    #[test]
+    fn test_admit_passes_unlimited_messages() {
+        let mut limiter = limiter(false);
+        let now = Instant::now();
+
+        assert!(limiter.admit(message(0, (1, 1), &[1]), now).is_some());
+        assert!(limiter.admit(message(0, (1, 1), &[2]), now).is_some());
+        assert_eq!(limiter.next_deadline(), None);
+    }
+
+    #[test]
+    fn test_admit_keeps_latest_message_until_due() {
+        let mut limiter = limiter(false);
+        let now = Instant::now();
+
+        assert!(limiter.admit(message(30, (1, 1), &[1]), now).is_some());
+        assert!(limiter.admit(message(30, (1, 1), &[2]), now).is_none());
+        assert!(limiter.admit(message(30, (1, 1), &[3]), now).is_none());
+
+        let deadline = now + Duration::from_millis(100);
+        assert_eq!(limiter.next_deadline(), Some(deadline));
+        assert!(limiter.take_due(now).is_empty());
+        assert_eq!(limiter.pending(), 1);
+        assert_eq!(limiter.take_superseded(), 1);
+        assert_eq!(limiter.take_superseded(), 0);
+
+        let due = limiter.take_due(deadline);
+        assert_eq!(due.len(), 1);
+        assert_eq!(due[0].data.as_ref(), &[3]);
+        assert_eq!(limiter.next_deadline(), None);
+    }
+
+    #[test]
+    fn test_take_all_ignores_deadlines() {
+        let mut limiter = limiter(false);
+        let now = Instant::now();
+
+        assert!(limiter.admit(message(30, (1, 1), &[1]), now).is_some());
+        assert!(limiter.admit(message(30, (1, 1), &[2]), now).is_none());
+
+        let held = limiter.take_all();
+        assert_eq!(held.len(), 1);
+        assert_eq!(held[0].data.as_ref(), &[2]);
+        assert_eq!(limiter.next_deadline(), None);
+    }
+
+    #[test]
+    fn test_admit_after_interval_sends_right_away() {
+        let mut limiter = limiter(false);
+        let now = Instant::now();
+
+        assert!(limiter.admit(message(30, (1, 1), &[1]), now).is_some());
+        let later = now + Duration::from_millis(100);
+        assert!(limiter.admit(message(30, (1, 1), &[2]), later).is_some());
+    }
+
+    #[test]
+    fn test_admit_shares_slot_between_components() {
+        let mut limiter = limiter(false);
+        let now = Instant::now();
+
+        assert!(limiter.admit(message(30, (1, 1), &[1]), now).is_some());
+        assert!(limiter.admit(message(30, (2, 1), &[2]), now).is_none());
+    }
+
+    #[test]
+    fn test_admit_per_component() {
+        let mut limiter = limiter(true);
+        let now = Instant::now();
+
+        assert!(limiter.admit(message(30, (1, 1), &[1]), now).is_some());
+        assert!(limiter.admit(message(30, (2, 1), &[2]), now).is_some());
+        assert!(limiter.admit(message(30, (1, 1), &[3]), now).is_none());
+    }
+
+    #[test]
+    fn test_new_ignores_invalid_rates() {
+        let mut limiter = RateLimiter::new(&[Settings {
+            message_id: 30,
+            max_rate: 0.0,
+            per_component: false,
+        }]);
+        let now = Instant::now();
+
+        assert!(limiter.admit(message(30, (1, 1), &[1]), now).is_some());
+        assert!(limiter.admit(message(30, (1, 1), &[2]), now).is_some());
+    }
+}
@@ -1,17 +1,46 @@
-use super::{target_database::TargetDatabase, transmitter, Name};
-use crate::{log_error::LogError, mavlink, router};
-use log::{debug, error};
-use std::sync::Arc;
-use tokio::sync::mpsc;
+use super::{
+    id_mapping::IdMapping, link_stats::LinkStats, mirror::Mirror, target_database::TargetDatabase,
+    transmitter, Name,
+};
+use crate::{
+    log_error::{LogError, SuppressedLog},
+    mavlink,
+    metrics::EndpointMetrics,
+    router,
+};
+use log::{debug, error, warn};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+use tokio::{sync::mpsc, time::Instant};
+
+// A misbehaving or spoofing device can send lots of messages with an invalid or taken sender.
+const SENDER_LOG_INTERVAL: Duration = Duration::from_secs(10);
+
+/// What to do with messages whose sender uses the reserved ID 0. Such senders are counted, but
+/// never learned as targets, since ID 0 would match the traffic targeted at anyone.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum InvalidSenderPolicy {
+    Forward,
+    Drop,
+    /// Kept for existing configurations, the same as `Forward`.
+    #[default]
+    ForwardWithoutLearning,
+}
 
 #[derive(Debug, thiserror::Error)]
 pub enum ReceiverError {
-    #[error("[{0}] Failed to receive message")]
-    Receive(Name, #[source] std::io::Error),
-    #[error("[{0}] Failed to deserialize message")]
-    Deserialization(Name, #[source] mavlink::DeserializationError),
+    #[error("[{0}] Failed to receive message{}", transmitter::describe_peer("from", .1))]
+    Receive(Name, Option<SocketAddr>, #[source] std::io::Error),
+    #[error("[{0}] Failed to deserialize message{}", transmitter::describe_peer("from", .1))]
+    Deserialization(
+        Name,
+        Option<SocketAddr>,
+        #[source] mavlink::DeserializationError,
+    ),
     #[error("[{0}] Failed to send message to router")]
-    SendToRouter(Name, #[source] mpsc::error::SendError<mavlink::Message>),
+    SendToRouter(Name, #[source] mpsc::error::SendError<router::Routed>),
 }
 
 pub struct Receiver {
@@ -20,15 +49,28 @@ pub struct Receiver {
     discovered_targets: Arc<TargetDatabase>,
     msg_tx: router::RouterTx,
     deserializer: Arc<mavlink::Deserializer>,
+    link_stats: Arc<LinkStats>,
+    id_mapping: Arc<IdMapping>,
+    invalid_senders: InvalidSenderPolicy,
+    invalid_sender_log: Mutex<SuppressedLog>,
+    rejected_sender_log: Mutex<SuppressedLog>,
+    mirror: Option<Arc<Mirror>>,
+    metrics: Arc<EndpointMetrics>,
 }
 
 impl Receiver {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         name: Name,
         receiver: transmitter::Receiver,
         discovered_targets: Arc<TargetDatabase>,
         msg_tx: router::RouterTx,
         deserializer: Arc<mavlink::Deserializer>,
+        link_stats: Arc<LinkStats>,
+        id_mapping: Arc<IdMapping>,
+        invalid_senders: InvalidSenderPolicy,
+        mirror: Option<Arc<Mirror>>,
+        metrics: Arc<EndpointMetrics>,
     ) -> Self {
         Self {
             name,
@@ -36,31 +78,63 @@ impl Receiver {
             discovered_targets,
             msg_tx,
             deserializer,
+            link_stats,
+            id_mapping,
+            invalid_senders,
+            invalid_sender_log: Mutex::new(SuppressedLog::new(SENDER_LOG_INTERVAL)),
+            rejected_sender_log: Mutex::new(SuppressedLog::new(SENDER_LOG_INTERVAL)),
+            mirror,
+            metrics,
         }
     }
 
-    fn deserialize(&self, data: transmitter::Data) -> Result<mavlink::Message, ReceiverError> {
+    fn deserialize(
+        &self,
+        data: transmitter::Data,
+    ) -> Result<Option<mavlink::Message>, ReceiverError> {
         let (msg, addr) = data;
+        if let Some(mirror) = &self.mirror {
+            mirror.received(&msg);
+        }
+        self.metrics.packet_received(msg.len());
         self.deserializer
             .deserialize(msg)
-            .inspect(|_| debug!("[{}] Received message from: {}", self.name, addr))
-            .inspect(|msg| self.validate_and_update_db(msg, addr))
-            .map_err(|e| ReceiverError::Deserialization(self.name.clone(), e))
+            .inspect(|_| debug!(peer:% = addr, endpoint = &*self.name; "[{}] Received message from: {}", self.name, addr))
+            .map(|msg| {
+                self.id_mapping
+                    .ingress(msg)
+                    .filter(|msg| self.validate_and_update_db(msg, addr))
+            })
+            .inspect(|msg| {
+                if let Some(msg) = msg {
+                    self.link_stats.record(msg);
+                    self.metrics.message_received(msg.msg_id);
+                }
+            })
+            .inspect_err(|e| self.metrics.deserialization_error(e))
+            .map_err(|e| {
+                ReceiverError::Deserialization(self.name.clone(), transmitter::peer(addr), e)
+            })
     }
 
     pub async fn run(&mut self) {
         while let Some(data) = self.receiver.recv().await {
+            let peer = match &data {
+                Ok((_, addr)) => transmitter::peer(*addr),
+                Err(e) => e.peer,
+            };
             if let Some(msg) = data
-                .map_err(|e| ReceiverError::Receive(self.name.clone(), e))
+                .map_err(|e| ReceiverError::Receive(self.name.clone(), e.peer, e.error))
                 .and_then(|data| self.deserialize(data))
-                .log_error()
+                .log_error_on(&self.name, peer)
+                .flatten()
             {
                 if self
                     .msg_tx
-                    .send(msg)
+                    .send((self.name.clone(), msg))
                     .await
                     .map_err(|e| ReceiverError::SendToRouter(self.name.clone(), e))
-                    .log_error()
+                    .log_error_on(&self.name, None)
                     .is_none()
                 {
                     // If the router is gone, we should stop receiving messages
@@ -70,15 +144,94 @@ impl Receiver {
         }
     }
 
-    fn validate_and_update_db(&self, msg: &mavlink::Message, addr: std::net::SocketAddr) {
-        if msg.routing_info.sender.is_valid_sender() {
-            self.discovered_targets
-                .insert_or_update(msg.routing_info.sender, addr);
-        } else {
-            error!(
-                "[{}] Received message from '{}' with invalid sender id: {}",
-                self.name, addr, msg.routing_info.sender
-            );
+    // Returns whether the message should be forwarded to the router.
+    fn validate_and_update_db(&self, msg: &mavlink::Message, addr: std::net::SocketAddr) -> bool {
+        if !msg.routing_info.sender.is_valid_sender() {
+            self.link_stats.record_invalid_sender();
+            if let Some(suppressed) = self.invalid_sender_log.lock().should_log(Instant::now()) {
+                error!(
+                    peer:% = addr, endpoint = &*self.name;
+                    "[{}] Received message from '{}' with invalid sender id: {} ({} more suppressed)",
+                    self.name, addr, msg.routing_info.sender, suppressed
+                );
+            }
+            return match self.invalid_senders {
+                InvalidSenderPolicy::Forward | InvalidSenderPolicy::ForwardWithoutLearning => true,
+                InvalidSenderPolicy::Drop => false,
+            };
+        }
+        if !self
+            .discovered_targets
+            .insert_or_update(msg.routing_info.sender, addr)
+        {
+            if let Some(suppressed) = self.rejected_sender_log.lock().should_log(Instant::now()) {
+                warn!(
+                    peer:% = addr, endpoint = &*self.name;
+                    "[{}] Dropping message from '{}' using id {}, which is locked or in use elsewhere ({} more suppressed)",
+                    self.name, addr, msg.routing_info.sender, suppressed
+                );
+            }
+            return false;
         }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::endpoint::target_database;
+
+    fn receiver(invalid_senders: InvalidSenderPolicy) -> Receiver {
+        let name: Name = "vehicle".into();
+        let deserializer = Arc::new(mavlink::Deserializer::new(Default::default()));
+        let targets = Arc::new(TargetDatabase::new(
+            name.clone(),
+            target_database::Settings::default(),
+            &[],
+        ));
+        let (_, transmitter_rx) = mpsc::channel(1);
+        let (router_tx, _) = mpsc::channel(1);
+        Receiver::new(
+            name.clone(),
+            transmitter_rx,
+            targets.clone(),
+            router_tx,
+            deserializer.clone(),
+            Arc::new(LinkStats::new(name.clone())),
+            Arc::new(IdMapping::new(name.clone(), Vec::new(), deserializer)),
+            invalid_senders,
+            None,
+            Arc::new(EndpointMetrics::new(name, targets, Default::default())),
+        )
+    }
+
+    fn message(sender: (u8, u8), target: (u8, u8)) -> mavlink::Message {
+        mavlink::Message {
+            routing_info: mavlink::RoutingInfo {
+                sender: sender.into(),
+                target: target.into(),
+            },
+            msg_id: 76,
+            seq: 0,
+            checksum: 0,
+            data: [0].into(),
+        }
+    }
+
+    #[test]
+    fn test_forwarded_invalid_sender_is_not_learned() {
+        let receiver = receiver(InvalidSenderPolicy::Forward);
+        let addr: SocketAddr = ([192, 168, 1, 10], 14550).into();
+
+        assert!(receiver.validate_and_update_db(&message((0, 1), (0, 0)), addr));
+        assert!(receiver.validate_and_update_db(&message((1, 0), (0, 0)), addr));
+        assert_eq!(receiver.link_stats.invalid_senders(), 2);
+
+        let unrelated = message((255, 190), (1, 1));
+        assert!(receiver
+            .discovered_targets
+            .get_target_addresses(&unrelated.routing_info)
+            .is_empty());
     }
 }
@@ -1,47 +1,209 @@
-use super::{target_database::TargetDatabase, transmitter, Name};
-use crate::{log_error::LogError, mavlink};
+use super::{
+    id_mapping::IdMapping, mirror::Mirror, priority::Priorities, queue::EndpointRx,
+    rate_limiter::RateLimiter, shaper::Shaper, target_database::TargetDatabase, transmitter,
+    EndpointSettings, Name,
+};
+use crate::{
+    log_error::LogError,
+    mavlink,
+    metrics::{DropReason, EndpointMetrics},
+};
 use log::debug;
-use std::sync::Arc;
-use tokio::sync::mpsc;
+use std::{net::SocketAddr, sync::Arc};
+use tokio::{
+    sync::mpsc::error::SendError,
+    time::{sleep_until, Instant},
+};
+
+pub type Packet = (Arc<[u8]>, Vec<SocketAddr>);
+
+#[derive(Debug, thiserror::Error)]
+pub enum SenderError {
+    #[error("[{0}] Failed to pass message to transmitter")]
+    Transmit(Name, #[source] SendError<transmitter::Data>),
+}
 
 pub struct Sender {
     name: Name,
     sender: transmitter::Sender,
     discovered_targets: Arc<TargetDatabase>,
-    msg_rx: mpsc::Receiver<mavlink::Message>,
+    msg_rx: EndpointRx,
+    rate_limiter: RateLimiter,
+    priorities: Priorities,
+    shaper: Option<Shaper<Packet>>,
+    id_mapping: Arc<IdMapping>,
+    sniffer: bool,
+    // The other end of point-to-point links like serial ports, which needn't be learned first.
+    peer: Option<SocketAddr>,
+    mirror: Option<Arc<Mirror>>,
+    metrics: Arc<EndpointMetrics>,
 }
 
 impl Sender {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         name: Name,
         sender: transmitter::Sender,
         discovered_targets: Arc<TargetDatabase>,
-        msg_rx: mpsc::Receiver<mavlink::Message>,
+        msg_rx: EndpointRx,
+        settings: &EndpointSettings,
+        id_mapping: Arc<IdMapping>,
+        mirror: Option<Arc<Mirror>>,
+        metrics: Arc<EndpointMetrics>,
     ) -> Self {
         Self {
             name,
             sender,
             discovered_targets,
             msg_rx,
+            rate_limiter: RateLimiter::new(&settings.rate_limits),
+            priorities: Priorities::new(&settings.priorities),
+            shaper: settings
+                .bandwidth
+                .as_ref()
+                .map(|bandwidth| Shaper::new(bandwidth, Instant::now())),
+            id_mapping,
+            sniffer: settings.sniffer,
+            peer: match settings.kind {
+                transmitter::Settings::Serial(_) => Some(transmitter::serial::PEER),
+                _ => None,
+            },
+            mirror,
+            metrics,
         }
     }
 
-    async fn send(&self, msg: mavlink::Message) {
-        for target in self
-            .discovered_targets
-            .get_target_addresses(&msg.routing_info)
-        {
-            debug!("[{}] Sending message to: {}", self.name, target);
-            self.sender
-                .send((msg.data.clone(), target))
-                .await
-                .log_error();
+    async fn send(&mut self, msg: mavlink::Message) {
+        let mut targets = if self.sniffer {
+            self.discovered_targets
+                .get_all_addresses(msg.routing_info.sender)
+        } else {
+            self.discovered_targets
+                .get_target_addresses(&msg.routing_info)
+        };
+        if let Some(peer) = self.peer {
+            // Broadcasts go out before anything was heard from the other end, but messages
+            // never go back to where they came from.
+            let is_wanted = !targets.is_empty() || msg.routing_info.target.is_broadcast();
+            targets = if is_wanted
+                && !self
+                    .discovered_targets
+                    .is_behind(msg.routing_info.sender, peer)
+            {
+                vec![peer]
+            } else {
+                Vec::new()
+            };
+        }
+        if targets.is_empty() {
+            return;
+        }
+        let msg = match self.id_mapping.egress(msg) {
+            Some(msg) => msg,
+            None => return,
+        };
+        self.metrics.message_sent(msg.msg_id);
+
+        match &mut self.shaper {
+            Some(shaper) => {
+                let cost = msg.data.len() * targets.len();
+                let priority = self.priorities.get(msg.msg_id);
+                shaper.push((msg.data, targets), cost, priority, Instant::now());
+            }
+            None => self.transmit((msg.data, targets)).await,
         }
     }
 
-    pub async fn run(&mut self) {
-        while let Some(msg) = self.msg_rx.recv().await {
+    async fn transmit(&self, (data, targets): Packet) {
+        if let Some(mirror) = &self.mirror {
+            mirror.sent(&data);
+        }
+        for target in targets {
+            debug!(peer:% = target, endpoint = &*self.name; "[{}] Sending message to: {}", self.name, target);
+            self.metrics.packet_sent(data.len());
+            let res = self.sender.send((data.clone(), target)).await;
+            if res.is_err() {
+                self.metrics.dropped(DropReason::SendFailed, 1);
+            }
+            res.map_err(|e| SenderError::Transmit(self.name.clone(), e))
+                .log_error_on(&self.name, transmitter::peer(target));
+        }
+    }
+
+    async fn flush_shaper(&mut self) {
+        let now = Instant::now();
+        let ready = match &mut self.shaper {
+            Some(shaper) => {
+                let dropped = shaper.drop_expired(now);
+                if dropped > 0 {
+                    self.metrics.dropped(DropReason::Bandwidth, dropped);
+                    debug!(
+                        endpoint = &*self.name;
+                        "[{}] Dropped {} messages waiting for bandwidth",
+                        self.name, dropped
+                    );
+                }
+                shaper.pop_ready(now)
+            }
+            None => return,
+        };
+
+        for packet in ready {
+            self.transmit(packet).await;
+        }
+    }
+
+    // Sends everything held back for rate limits or bandwidth, once no more messages can arrive.
+    async fn flush(&mut self) {
+        for msg in self.rate_limiter.take_all() {
             self.send(msg).await;
         }
+        let held = self
+            .shaper
+            .as_mut()
+            .map(Shaper::take_all)
+            .unwrap_or_default();
+        for packet in held {
+            self.transmit(packet).await;
+        }
+    }
+
+    fn next_deadline(&self) -> Option<Instant> {
+        let shaper_deadline = self.shaper.as_ref().and_then(Shaper::next_deadline);
+        [self.rate_limiter.next_deadline(), shaper_deadline]
+            .into_iter()
+            .flatten()
+            .min()
+    }
+
+    pub async fn run(&mut self) {
+        loop {
+            let deadline = self.next_deadline();
+            tokio::select! {
+                msg = self.msg_rx.recv() => match msg {
+                    Some(msg) => {
+                        if let Some(msg) = self.rate_limiter.admit(msg, Instant::now()) {
+                            self.send(msg).await;
+                        }
+                        let superseded = self.rate_limiter.take_superseded();
+                        if superseded > 0 {
+                            self.metrics.dropped(DropReason::RateLimit, superseded);
+                        }
+                    }
+                    None => {
+                        self.flush().await;
+                        break;
+                    }
+                },
+                _ = sleep_until(deadline.unwrap_or_else(Instant::now)), if deadline.is_some() => {
+                    for msg in self.rate_limiter.take_due(Instant::now()) {
+                        self.send(msg).await;
+                    }
+                }
+            }
+            self.flush_shaper().await;
+            let shaped = self.shaper.as_ref().map_or(0, Shaper::len);
+            self.metrics.set_held(self.rate_limiter.pending() + shaped);
+        }
     }
 }
@@ -0,0 +1,244 @@
+use serde::{Deserialize, Serialize};
+use std::{collections::VecDeque, time::Duration};
+use tokio::time::Instant;
+
+use super::priority::Priority;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Settings {
+    /// The sustained number of bytes per second the link can carry.
+    pub bytes_per_second: u32,
+    /// The number of bytes that may be sent at once, defaults to one second worth of data.
+    #[serde(default)]
+    pub burst: Option<u32>,
+    /// How long normal and low priority messages may wait for bandwidth before being dropped.
+    #[serde(default = "default_max_delay_ms")]
+    pub max_delay_ms: u64,
+    /// How long high priority messages may wait, so they can't pile up on a saturated link.
+    #[serde(default = "default_high_priority_max_delay_ms")]
+    pub high_priority_max_delay_ms: u64,
+}
+
+fn default_max_delay_ms() -> u64 {
+    500
+}
+
+fn default_high_priority_max_delay_ms() -> u64 {
+    2000
+}
+
+struct Queued<T> {
+    item: T,
+    cost: usize,
+    queued_at: Instant,
+}
+
+/// A token bucket which sends queued items in order of their priority.
+pub struct Shaper<T> {
+    rate: f64,
+    capacity: f64,
+    tokens: f64,
+    last_refill: Instant,
+    max_delay: Duration,
+    high_priority_max_delay: Duration,
+    queues: [VecDeque<Queued<T>>; 3],
+}
+
+impl<T> Shaper<T> {
+    pub fn new(settings: &Settings, now: Instant) -> Self {
+        let rate = settings.bytes_per_second.max(1) as f64;
+        let capacity = settings
+            .burst
+            .map(|burst| burst.max(1) as f64)
+            .unwrap_or(rate);
+
+        Self {
+            rate,
+            capacity,
+            tokens: capacity,
+            last_refill: now,
+            max_delay: Duration::from_millis(settings.max_delay_ms),
+            high_priority_max_delay: Duration::from_millis(settings.high_priority_max_delay_ms),
+            queues: Default::default(),
+        }
+    }
+
+    pub fn push(&mut self, item: T, cost: usize, priority: Priority, now: Instant) {
+        self.queues[priority.index()].push_back(Queued {
+            item,
+            cost,
+            queued_at: now,
+        });
+    }
+
+    /// Drops all items which waited for longer than the maximum delay of their priority.
+    /// Returns the number of dropped items.
+    pub fn drop_expired(&mut self, now: Instant) -> usize {
+        let (max_delay, high_priority_max_delay) = (self.max_delay, self.high_priority_max_delay);
+        self.queues
+            .iter_mut()
+            .enumerate()
+            .map(|(index, queue)| {
+                let max_delay = if index == Priority::High.index() {
+                    high_priority_max_delay
+                } else {
+                    max_delay
+                };
+                let len = queue.len();
+                queue.retain(|q| now.duration_since(q.queued_at) <= max_delay);
+                len - queue.len()
+            })
+            .sum()
+    }
+
+    /// Takes as many items as the available bandwidth allows, highest priority first.
+    pub fn pop_ready(&mut self, now: Instant) -> Vec<T> {
+        self.refill(now);
+
+        let mut ready = Vec::new();
+        while let Some(queue) = self.queues.iter_mut().find(|q| !q.is_empty()) {
+            let cost = queue[0].cost as f64;
+            // Items bigger than the bucket would never fit, so let them through once it is full.
+            if self.tokens < cost.min(self.capacity) {
+                break;
+            }
+            self.tokens -= cost;
+            ready.extend(queue.pop_front().map(|q| q.item));
+        }
+        ready
+    }
+
+    /// The number of items waiting for bandwidth.
+    pub fn len(&self) -> usize {
+        self.queues.iter().map(VecDeque::len).sum()
+    }
+
+    /// Takes all queued items regardless of the available bandwidth, highest priority first.
+    pub fn take_all(&mut self) -> Vec<T> {
+        self.queues
+            .iter_mut()
+            .flat_map(|queue| queue.drain(..).map(|q| q.item))
+            .collect()
+    }
+
+    /// The point in time at which enough bandwidth is available for the next item.
+    pub fn next_deadline(&self) -> Option<Instant> {
+        let cost = self.queues.iter().find_map(|q| q.front())?.cost as f64;
+        let missing = cost.min(self.capacity) - self.tokens;
+        let wait = Duration::from_secs_f64(missing.max(0.0) / self.rate);
+        Some(self.last_refill + wait)
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity);
+        self.last_refill = now;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shaper(bytes_per_second: u32, burst: u32) -> Shaper<u32> {
+        let settings = Settings {
+            bytes_per_second,
+            burst: Some(burst),
+            max_delay_ms: 500,
+            high_priority_max_delay_ms: 2000,
+        };
+        Shaper::new(&settings, Instant::now())
+    }
+
+    #[test]
+    fn test_pop_ready_sends_within_burst() {
+        let mut shaper = shaper(100, 100);
+        let now = shaper.last_refill;
+
+        shaper.push(1, 50, Priority::Normal, now);
+        shaper.push(2, 50, Priority::Normal, now);
+        shaper.push(3, 50, Priority::Normal, now);
+
+        assert_eq!(shaper.pop_ready(now), vec![1, 2]);
+        assert_eq!(
+            shaper.next_deadline(),
+            Some(now + Duration::from_millis(500))
+        );
+
+        assert_eq!(shaper.pop_ready(now + Duration::from_millis(500)), vec![3]);
+        assert_eq!(shaper.next_deadline(), None);
+    }
+
+    #[test]
+    fn test_pop_ready_prioritises() {
+        let mut shaper = shaper(100, 100);
+        let now = shaper.last_refill;
+
+        shaper.push(1, 60, Priority::Low, now);
+        shaper.push(2, 60, Priority::Normal, now);
+        shaper.push(3, 30, Priority::High, now);
+
+        assert_eq!(shaper.pop_ready(now), vec![3, 2]);
+        assert_eq!(shaper.pop_ready(now + Duration::from_millis(600)), vec![1]);
+    }
+
+    #[test]
+    fn test_pop_ready_lets_oversized_items_through_when_full() {
+        let mut shaper = shaper(100, 100);
+        let now = shaper.last_refill;
+
+        shaper.push(1, 150, Priority::Normal, now);
+        shaper.push(2, 10, Priority::Normal, now);
+
+        assert_eq!(shaper.pop_ready(now), vec![1]);
+        // The bucket is in debt now and needs to recover before sending again.
+        assert_eq!(
+            shaper.next_deadline(),
+            Some(now + Duration::from_millis(600))
+        );
+        assert_eq!(shaper.pop_ready(now + Duration::from_millis(600)), vec![2]);
+    }
+
+    #[test]
+    fn test_take_all_ignores_bandwidth() {
+        let mut shaper = shaper(100, 100);
+        let now = shaper.last_refill;
+
+        shaper.push(1, 100, Priority::Low, now);
+        shaper.push(2, 100, Priority::Normal, now);
+        shaper.push(3, 100, Priority::High, now);
+
+        assert_eq!(shaper.take_all(), vec![3, 2, 1]);
+        assert_eq!(shaper.next_deadline(), None);
+    }
+
+    #[test]
+    fn test_drop_expired_keeps_high_priority_longer() {
+        let mut shaper = shaper(10, 10);
+        let now = shaper.last_refill;
+
+        shaper.push(1, 100, Priority::High, now);
+        shaper.push(2, 100, Priority::Normal, now);
+        shaper.push(3, 100, Priority::Low, now);
+
+        assert_eq!(shaper.drop_expired(now + Duration::from_millis(500)), 0);
+        assert_eq!(shaper.drop_expired(now + Duration::from_millis(501)), 2);
+        assert_eq!(shaper.pop_ready(now + Duration::from_millis(501)), vec![1]);
+        assert_eq!(shaper.next_deadline(), None);
+    }
+
+    #[test]
+    fn test_drop_expired_bounds_high_priority_backlog() {
+        let mut shaper = shaper(10, 10);
+        let now = shaper.last_refill;
+
+        // A link which is saturated by high priority traffic alone
+        for i in 0..10 {
+            shaper.push(i, 100, Priority::High, now);
+        }
+        assert_eq!(shaper.pop_ready(now), vec![0]);
+        assert_eq!(shaper.drop_expired(now + Duration::from_millis(2000)), 0);
+        assert_eq!(shaper.drop_expired(now + Duration::from_millis(2001)), 9);
+        assert_eq!(shaper.next_deadline(), None);
+    }
+}
@@ -1,41 +1,309 @@
-use parking_lot::{RwLock, RwLockUpgradableReadGuard as ReadGuard};
-use std::net::SocketAddr;
+use log::warn;
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, net::SocketAddr, time::Duration};
+use tokio::time::Instant;
 
+use super::Name;
 use crate::mavlink;
 
+// An address which was used within this window still owns its ID.
+const CONFLICT_WINDOW: Duration = Duration::from_secs(5);
+// Two addresses fighting over an ID would log on every message otherwise.
+const REPORT_INTERVAL: Duration = Duration::from_secs(10);
+// How often the last use of an address is written, so most messages only need a read lock.
+const REFRESH_INTERVAL: Duration = Duration::from_millis(100);
+
+/// What to do when a component ID shows up from a second address.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ConflictPolicy {
+    /// Route to the address which used the ID most recently.
+    #[default]
+    LastWins,
+    /// Keep routing to the first address until it falls silent, dropping messages from others.
+    FirstWins,
+    /// Route to every address which used the ID.
+    AllowMultiple,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Settings {
+    #[serde(default)]
+    pub policy: ConflictPolicy,
+    /// How long an address may be silent before it stops receiving traffic for its ID while
+    /// other addresses of the ID are active, when multiple addresses are allowed.
+    #[serde(default = "default_address_timeout_ms")]
+    pub address_timeout_ms: u64,
+}
+
+fn default_address_timeout_ms() -> u64 {
+    5000
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            policy: ConflictPolicy::default(),
+            address_timeout_ms: default_address_timeout_ms(),
+        }
+    }
+}
+
+/// Restricts a system or component ID to a single endpoint and optionally a single address.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct IdLock {
+    pub sys_id: u8,
+    /// Locks all components of the system if not set.
+    #[serde(default)]
+    pub comp_id: Option<u8>,
+    pub endpoint: String,
+    #[serde(default)]
+    pub address: Option<SocketAddr>,
+}
+
+impl IdLock {
+    fn matches(&self, id: mavlink::SysCompId) -> bool {
+        id.sys_id() == self.sys_id && self.comp_id.is_none_or(|c| c == id.comp_id())
+    }
+
+    fn forbids(&self, endpoint: &str, addr: SocketAddr) -> bool {
+        self.endpoint != endpoint || self.address.is_some_and(|address| address != addr)
+    }
+}
+
+struct Target {
+    id: mavlink::SysCompId,
+    addr: SocketAddr,
+    last_seen: Instant,
+}
+
+#[derive(Default)]
+struct Targets {
+    targets: Vec<Target>,
+    reported: HashMap<mavlink::SysCompId, Instant>,
+    /// Routes which were added by hand, and are never forgotten or replaced.
+    static_routes: Vec<(mavlink::SysCompId, SocketAddr)>,
+}
+
+/// A known address of a component.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Route {
+    pub id: mavlink::SysCompId,
+    pub addr: SocketAddr,
+    /// How long ago the component was last heard from the address, not set for static routes.
+    pub last_seen: Option<Duration>,
+}
+
 pub struct TargetDatabase {
-    targets: RwLock<Vec<(mavlink::SysCompId, SocketAddr)>>,
+    name: Name,
+    settings: Settings,
+    locks: Vec<IdLock>,
+    targets: RwLock<Targets>,
 }
 
 impl TargetDatabase {
-    pub fn new() -> Self {
+    pub fn new(name: Name, settings: Settings, locks: &[IdLock]) -> Self {
         Self {
-            targets: RwLock::new(Vec::new()),
+            name,
+            settings,
+            locks: locks.to_vec(),
+            targets: RwLock::new(Targets::default()),
         }
     }
 
-    pub fn insert_or_update(&self, sender: mavlink::SysCompId, addr: SocketAddr) {
-        let targets = self.targets.upgradable_read();
-        match targets.iter().position(|(t, _)| t == &sender) {
-            Some(index) if targets[index].1 != addr => {
-                let mut targets = ReadGuard::upgrade(targets);
-                targets[index] = (sender, addr);
-            }
-            None => {
-                let mut targets = ReadGuard::upgrade(targets);
-                targets.push((sender, addr));
-            }
-            _ => {}
+    /// Learns the address of the sender. Returns false if the message must be dropped, because
+    /// the sender is locked out of the address or another address owns its ID.
+    pub fn insert_or_update(&self, sender: mavlink::SysCompId, addr: SocketAddr) -> bool {
+        self.learn(sender, addr, Instant::now())
+    }
+
+    fn learn(&self, sender: mavlink::SysCompId, addr: SocketAddr, now: Instant) -> bool {
+        if self
+            .locks
+            .iter()
+            .any(|lock| lock.matches(sender) && lock.forbids(&self.name, addr))
+        {
+            return false;
+        }
+
+        let is_fresh = |t: &Target| {
+            t.id == sender && t.addr == addr && now.duration_since(t.last_seen) < REFRESH_INTERVAL
+        };
+        if self.targets.read().targets.iter().any(is_fresh) {
+            return true;
+        }
+
+        let mut targets = self.targets.write();
+        if self.settings.policy == ConflictPolicy::AllowMultiple {
+            // Forget addresses which fell silent while the ID is in use elsewhere.
+            let timeout = Duration::from_millis(self.settings.address_timeout_ms);
+            targets.targets.retain(|t| {
+                t.id != sender || t.addr == addr || now.duration_since(t.last_seen) < timeout
+            });
+        }
+        if let Some(target) = targets
+            .targets
+            .iter_mut()
+            .find(|t| t.id == sender && t.addr == addr)
+        {
+            target.last_seen = now;
+            return true;
         }
+
+        let active = targets
+            .targets
+            .iter()
+            .filter(|t| t.id == sender && now.duration_since(t.last_seen) < CONFLICT_WINDOW)
+            .map(|t| t.addr)
+            .collect::<Vec<_>>();
+        if !active.is_empty() {
+            targets.report_conflict(&self.name, sender, &active, addr, now);
+        }
+
+        match self.settings.policy {
+            ConflictPolicy::LastWins => targets.targets.retain(|t| t.id != sender),
+            ConflictPolicy::FirstWins if !active.is_empty() => return false,
+            ConflictPolicy::FirstWins => targets.targets.retain(|t| t.id != sender),
+            ConflictPolicy::AllowMultiple => {}
+        }
+        targets.targets.push(Target {
+            id: sender,
+            addr,
+            last_seen: now,
+        });
+        true
     }
 
     pub fn get_target_addresses(&self, routing_info: &mavlink::RoutingInfo) -> Vec<SocketAddr> {
-        self.targets
-            .read()
+        self.target_addresses(routing_info, Instant::now())
+    }
+
+    /// The number of known component addresses.
+    pub fn target_count(&self) -> usize {
+        let targets = self.targets.read();
+        targets.targets.len() + targets.static_routes.len()
+    }
+
+    /// Every known address regardless of the components using it, except those of the sender,
+    /// which would get its own messages echoed back otherwise.
+    pub fn get_all_addresses(&self, sender: mavlink::SysCompId) -> Vec<SocketAddr> {
+        let targets = self.targets.read();
+        let routes = || {
+            let learned = targets.targets.iter().map(|t| (t.id, t.addr));
+            learned.chain(targets.static_routes.iter().copied())
+        };
+        let mut addresses = Vec::new();
+        for (_, addr) in routes() {
+            if !addresses.contains(&addr) && !routes().any(|route| route == (sender, addr)) {
+                addresses.push(addr);
+            }
+        }
+        addresses
+    }
+
+    /// Whether the component was seen at, or is statically routed to, the address.
+    pub fn is_behind(&self, id: mavlink::SysCompId, addr: SocketAddr) -> bool {
+        let targets = self.targets.read();
+        targets.targets.iter().any(|t| t.id == id && t.addr == addr)
+            || targets.static_routes.contains(&(id, addr))
+    }
+
+    /// Routes messages for the component to the address, in addition to any learned addresses.
+    /// Returns false if the route already exists.
+    pub fn add_static_route(&self, id: mavlink::SysCompId, addr: SocketAddr) -> bool {
+        let mut targets = self.targets.write();
+        if targets.static_routes.contains(&(id, addr)) {
+            return false;
+        }
+        targets.static_routes.push((id, addr));
+        true
+    }
+
+    /// Returns false if there was no such route.
+    pub fn remove_static_route(&self, id: mavlink::SysCompId, addr: SocketAddr) -> bool {
+        let mut targets = self.targets.write();
+        let len = targets.static_routes.len();
+        targets.static_routes.retain(|route| *route != (id, addr));
+        targets.static_routes.len() != len
+    }
+
+    /// All learned and static routes.
+    pub fn routes(&self) -> Vec<Route> {
+        let now = Instant::now();
+        let targets = self.targets.read();
+        let learned = targets.targets.iter().map(|t| Route {
+            id: t.id,
+            addr: t.addr,
+            last_seen: Some(now.duration_since(t.last_seen)),
+        });
+        let static_routes = targets.static_routes.iter().map(|&(id, addr)| Route {
+            id,
+            addr,
+            last_seen: None,
+        });
+        learned.chain(static_routes).collect()
+    }
+
+    fn target_addresses(
+        &self,
+        routing_info: &mavlink::RoutingInfo,
+        now: Instant,
+    ) -> Vec<SocketAddr> {
+        let timeout = Duration::from_millis(self.settings.address_timeout_ms);
+        let is_active = |t: &Target| now.duration_since(t.last_seen) < timeout;
+
+        let targets = self.targets.read();
+        let learned = targets
+            .targets
             .iter()
-            .filter(|(t, _)| routing_info.matches(*t))
-            .map(|(_, addr)| *addr)
-            .collect()
+            .filter(|t| routing_info.matches(t.id))
+            .filter(|t| {
+                // Silent addresses are only skipped if the ID is still reachable elsewhere.
+                self.settings.policy != ConflictPolicy::AllowMultiple
+                    || is_active(t)
+                    || !targets.targets.iter().any(|o| o.id == t.id && is_active(o))
+            })
+            .map(|t| t.addr);
+        let static_routes = targets
+            .static_routes
+            .iter()
+            .filter(|(id, _)| routing_info.matches(*id))
+            .map(|(_, addr)| *addr);
+
+        // Components sharing an address, like everything behind a serial port, get one copy
+        let mut addresses = Vec::new();
+        for addr in learned.chain(static_routes) {
+            if !addresses.contains(&addr) {
+                addresses.push(addr);
+            }
+        }
+        addresses
+    }
+}
+
+impl Targets {
+    fn report_conflict(
+        &mut self,
+        name: &str,
+        id: mavlink::SysCompId,
+        active: &[SocketAddr],
+        addr: SocketAddr,
+        now: Instant,
+    ) {
+        if self
+            .reported
+            .get(&id)
+            .is_some_and(|reported| now.duration_since(*reported) < REPORT_INTERVAL)
+        {
+            return;
+        }
+        warn!(
+            endpoint = name;
+            "[{}] Conflict for {}: used by '{}' while also in use by {:?}",
+            name, id, addr, active
+        );
+        self.reported.insert(id, now);
     }
 }
 
@@ -43,9 +311,20 @@ impl TargetDatabase {
 mod tests {
     use super::*;
 
+    fn database(policy: ConflictPolicy) -> TargetDatabase {
+        TargetDatabase::new(
+            "test".into(),
+            Settings {
+                policy,
+                ..Default::default()
+            },
+            &[],
+        )
+    }
+
     #[test]
     fn test_insert_or_update() -> Result<(), std::net::AddrParseError> {
-        let db = TargetDatabase::new();
+        let db = database(ConflictPolicy::LastWins);
         let sender = mavlink::SysCompId::from((1, 1));
         let target = mavlink::SysCompId::from((1, 2));
         let routing_info = mavlink::RoutingInfo { sender, target };
@@ -58,7 +337,7 @@ mod tests {
 
     #[test]
     fn test_insert_or_update_updates() -> Result<(), std::net::AddrParseError> {
-        let db = TargetDatabase::new();
+        let db = database(ConflictPolicy::LastWins);
         let sender = mavlink::SysCompId::from((1, 1));
         let target = mavlink::SysCompId::from((1, 2));
         let routing_info = mavlink::RoutingInfo { sender, target };
@@ -74,7 +353,7 @@ mod tests {
     #[test]
     fn test_insert_or_update_does_nothing_when_inserting_twice(
     ) -> Result<(), std::net::AddrParseError> {
-        let db = TargetDatabase::new();
+        let db = database(ConflictPolicy::LastWins);
         let sender = mavlink::SysCompId::from((1, 1));
         let target = mavlink::SysCompId::from((1, 2));
         let routing_info = mavlink::RoutingInfo { sender, target };
@@ -89,7 +368,7 @@ mod tests {
     #[test]
     fn test_get_matching_returns_empty_when_target_not_found(
     ) -> Result<(), std::net::AddrParseError> {
-        let db = TargetDatabase::new();
+        let db = database(ConflictPolicy::LastWins);
 
         let target = mavlink::SysCompId::from((1, 1));
         let addr1 = "127.0.0.1:14550".parse()?;
@@ -104,7 +383,7 @@ mod tests {
 
     #[test]
     fn test_get_matching_returns_matching_targets() -> Result<(), std::net::AddrParseError> {
-        let db = TargetDatabase::new();
+        let db = database(ConflictPolicy::LastWins);
 
         let target = mavlink::SysCompId::from((1, 1));
         let addr1 = "127.0.0.1:14550".parse()?;
@@ -124,4 +403,144 @@ mod tests {
         assert_eq!(db.get_target_addresses(&routing_info), vec![addr1, addr2]);
         Ok(())
     }
+
+    #[test]
+    fn test_first_wins_keeps_active_address() -> Result<(), std::net::AddrParseError> {
+        let db = database(ConflictPolicy::FirstWins);
+        let target = mavlink::SysCompId::from((1, 1));
+        let routing_info = mavlink::RoutingInfo {
+            sender: (255, 1).into(),
+            target,
+        };
+        let (first, second) = ("127.0.0.1:14550".parse()?, "127.0.0.1:14551".parse()?);
+        let now = Instant::now();
+
+        assert!(db.learn(target, first, now));
+        assert!(!db.learn(target, second, now + Duration::from_secs(1)));
+        assert_eq!(db.get_target_addresses(&routing_info), vec![first]);
+
+        // Once the first address falls silent, the ID moves on.
+        assert!(db.learn(target, second, now + CONFLICT_WINDOW));
+        assert_eq!(db.get_target_addresses(&routing_info), vec![second]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_allow_multiple_keeps_all_addresses() -> Result<(), std::net::AddrParseError> {
+        let db = database(ConflictPolicy::AllowMultiple);
+        let target = mavlink::SysCompId::from((1, 1));
+        let routing_info = mavlink::RoutingInfo {
+            sender: (255, 1).into(),
+            target,
+        };
+        let (first, second) = ("127.0.0.1:14550".parse()?, "127.0.0.1:14551".parse()?);
+
+        db.insert_or_update(target, first);
+        db.insert_or_update(target, second);
+        assert_eq!(db.get_target_addresses(&routing_info), vec![first, second]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_locks_restrict_endpoint_and_address() -> Result<(), std::net::AddrParseError> {
+        let addr = "127.0.0.1:14550".parse()?;
+        let lock = IdLock {
+            sys_id: 1,
+            comp_id: None,
+            endpoint: "vehicle".to_string(),
+            address: Some(addr),
+        };
+        let vehicle = TargetDatabase::new(
+            "vehicle".into(),
+            Settings::default(),
+            std::slice::from_ref(&lock),
+        );
+        let gcs = TargetDatabase::new("gcs".into(), Settings::default(), &[lock]);
+
+        assert!(vehicle.insert_or_update((1, 1).into(), addr));
+        assert!(!vehicle.insert_or_update((1, 2).into(), "127.0.0.1:14551".parse()?));
+        assert!(!gcs.insert_or_update((1, 1).into(), addr));
+        assert!(gcs.insert_or_update((2, 1).into(), addr));
+        Ok(())
+    }
+
+    #[test]
+    fn test_allow_multiple_skips_silent_addresses() -> Result<(), std::net::AddrParseError> {
+        let db = database(ConflictPolicy::AllowMultiple);
+        let gcs = mavlink::SysCompId::from((255, 190));
+        let routing_info = mavlink::RoutingInfo {
+            sender: (1, 1).into(),
+            target: gcs,
+        };
+        let (operator, supervisor) = ("127.0.0.1:14550".parse()?, "127.0.0.1:14551".parse()?);
+        let now = Instant::now();
+
+        db.learn(gcs, operator, now);
+        db.learn(gcs, supervisor, now);
+        assert_eq!(
+            db.target_addresses(&routing_info, now),
+            vec![operator, supervisor]
+        );
+
+        let later = now + Duration::from_millis(6000);
+        db.learn(gcs, supervisor, later);
+        assert_eq!(db.target_addresses(&routing_info, later), vec![supervisor]);
+
+        // Without any active address, the last known ones are still used.
+        let silent = later + Duration::from_millis(6000);
+        assert_eq!(db.target_addresses(&routing_info, silent), vec![supervisor]);
+
+        db.learn(gcs, operator, silent);
+        assert_eq!(db.target_addresses(&routing_info, silent), vec![operator]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_all_addresses() -> Result<(), std::net::AddrParseError> {
+        let db = database(ConflictPolicy::LastWins);
+        let (addr1, addr2) = ("127.0.0.1:14550".parse()?, "127.0.0.1:14551".parse()?);
+
+        db.insert_or_update((1, 1).into(), addr1);
+        db.insert_or_update((1, 2).into(), addr1);
+        db.insert_or_update((255, 1).into(), addr2);
+        assert_eq!(db.get_all_addresses((2, 1).into()), vec![addr1, addr2]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_all_addresses_skips_sender() -> Result<(), std::net::AddrParseError> {
+        let db = database(ConflictPolicy::LastWins);
+        let (addr1, addr2) = ("127.0.0.1:14550".parse()?, "127.0.0.1:14551".parse()?);
+
+        db.insert_or_update((1, 1).into(), addr1);
+        db.insert_or_update((255, 1).into(), addr2);
+        assert_eq!(db.get_all_addresses((1, 1).into()), vec![addr2]);
+        assert_eq!(db.get_all_addresses((255, 1).into()), vec![addr1]);
+        assert!(db.is_behind((1, 1).into(), addr1));
+        assert!(!db.is_behind((1, 1).into(), addr2));
+        Ok(())
+    }
+
+    #[test]
+    fn test_static_routes() -> Result<(), std::net::AddrParseError> {
+        let db = database(ConflictPolicy::LastWins);
+        let target = mavlink::SysCompId::from((1, 2));
+        let routing_info = mavlink::RoutingInfo {
+            sender: (255, 1).into(),
+            target,
+        };
+        let (learned, fixed) = ("127.0.0.1:14550".parse()?, "127.0.0.1:14551".parse()?);
+
+        assert!(db.add_static_route(target, fixed));
+        assert!(!db.add_static_route(target, fixed));
+        // Learning a new address for the ID doesn't replace the static route
+        db.insert_or_update(target, learned);
+        assert_eq!(db.get_target_addresses(&routing_info), vec![learned, fixed]);
+        assert_eq!(db.routes()[1].last_seen, None);
+
+        assert!(db.remove_static_route(target, fixed));
+        assert!(!db.remove_static_route(target, fixed));
+        assert_eq!(db.get_target_addresses(&routing_info), vec![learned]);
+        Ok(())
+    }
 }
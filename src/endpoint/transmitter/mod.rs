@@ -1,41 +1,298 @@
 use log::info;
-use std::{net::SocketAddr, sync::Arc};
-use tokio::sync::mpsc;
+use std::{
+    net::{Ipv4Addr, SocketAddr},
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+use tokio::{sync::mpsc, task::JoinHandle};
 
+use super::Name;
+use crate::{log_error::LogError, mavlink};
+
+pub mod serial;
 pub mod tcp;
 pub mod udp;
 
 type Result<T> = std::result::Result<T, std::io::Error>;
 pub type Data = (Arc<[u8]>, SocketAddr);
-pub type RecvResult = Result<Data>;
+pub type RecvResult = std::result::Result<Data, RecvError>;
+
+/// A failure to receive, with the peer it occurred on if it is known.
+#[derive(Debug)]
+pub struct RecvError {
+    pub peer: Option<SocketAddr>,
+    pub error: std::io::Error,
+}
+
+/// The address of the peer data was exchanged with, which serial ports don't have.
+pub fn peer(addr: SocketAddr) -> Option<SocketAddr> {
+    (addr != serial::PEER).then_some(addr)
+}
+
+/// Describes the peer of an error message, e.g. ` to 127.0.0.1:14550`, if there is one.
+pub fn describe_peer(preposition: &str, peer: &Option<SocketAddr>) -> String {
+    peer.map(|peer| format!(" {} {}", preposition, peer))
+        .unwrap_or_default()
+}
 
 pub type Sender = mpsc::Sender<Data>;
 pub type Receiver = mpsc::Receiver<RecvResult>;
+/// The tasks owning the sockets of a transmitter, which are closed once the tasks are aborted.
+#[derive(Default)]
+pub struct Tasks {
+    /// Tasks producing received data, which can be stopped without losing queued data.
+    pub receiving: Vec<JoinHandle<()>>,
+    /// Tasks consuming data to send, which finish once their channel is closed and drained.
+    pub sending: Vec<JoinHandle<()>>,
+}
+
+impl Tasks {
+    pub fn abort(&self) {
+        for task in self.receiving.iter().chain(&self.sending) {
+            task.abort();
+        }
+    }
+}
 
 #[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
 pub enum Settings {
     Udp(udp::Settings),
     Tcp(tcp::Settings),
+    Serial(serial::Settings),
+}
+
+impl std::fmt::Display for Settings {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Udp(settings) => write!(f, "udp://{}", settings.address),
+            Self::Tcp(settings) => write!(f, "tcp://{}", settings.address),
+            Self::Serial(settings) => write!(
+                f,
+                "serial://{}?baud={}",
+                settings.path.display(),
+                settings.baud
+            ),
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum TransmitterError {
+    #[error("[{0}] Failed to send message{}", describe_peer("to", .1))]
+    Send(Name, Option<SocketAddr>, #[source] std::io::Error),
+    #[error("[{0}] Failed to close connection to {1}")]
+    Close(Name, SocketAddr, #[source] std::io::Error),
+}
+
+// Logs a failed send and counts the message as dropped.
+fn report_send(
+    name: &Name,
+    peer: Option<SocketAddr>,
+    failed_sends: &AtomicU64,
+    res: std::io::Result<()>,
+) {
+    res.inspect_err(|_| {
+        failed_sends.fetch_add(1, Ordering::Relaxed);
+    })
+    .map_err(|e| TransmitterError::Send(name.clone(), peer, e))
+    .log_error_on(name, peer);
+}
+
+#[derive(Debug, PartialEq, thiserror::Error)]
+pub enum ParseError {
+    #[error("Missing scheme in '{0}', expected e.g. 'udp://0.0.0.0:14550'")]
+    MissingScheme(String),
+    #[error("Unsupported scheme '{0}'")]
+    UnsupportedScheme(String),
+    #[error("Options are not supported for {0} endpoints")]
+    Options(String),
+    #[error("Invalid address '{0}'")]
+    Address(String),
+    #[error("Missing device in '{0}', expected e.g. 'serial:///dev/ttyACM0?baud=57600'")]
+    MissingDevice(String),
+    #[error("Unknown option '{0}'")]
+    UnknownOption(String),
+    #[error("Invalid baud rate '{0}'")]
+    Baud(String),
+}
+
+/// Parses URLs like `udp://0.0.0.0:14550` or `tcp://:5760`, where an empty host binds to all
+/// interfaces, and `serial:///dev/ttyACM0?baud=921600`.
+impl FromStr for Settings {
+    type Err = ParseError;
+
+    fn from_str(url: &str) -> std::result::Result<Self, Self::Err> {
+        let (scheme, rest) = url
+            .split_once("://")
+            .ok_or_else(|| ParseError::MissingScheme(url.to_string()))?;
+        let scheme = scheme.to_lowercase();
+        match scheme.as_str() {
+            "udp" | "tcp" => {}
+            "serial" => return parse_serial(rest).map(Self::Serial),
+            _ => return Err(ParseError::UnsupportedScheme(scheme)),
+        }
+        if rest.contains('?') {
+            return Err(ParseError::Options(scheme));
+        }
+
+        let address = match rest.strip_prefix(':') {
+            Some(port) => port
+                .parse()
+                .ok()
+                .map(|port| SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), port)),
+            None => rest.parse().ok(),
+        }
+        .ok_or_else(|| ParseError::Address(rest.to_string()))?;
+
+        Ok(match scheme.as_str() {
+            "udp" => Self::Udp(udp::Settings { address }),
+            _ => Self::Tcp(tcp::Settings { address }),
+        })
+    }
+}
+
+fn parse_serial(rest: &str) -> std::result::Result<serial::Settings, ParseError> {
+    let (path, options) = rest.split_once('?').unwrap_or((rest, ""));
+    if path.is_empty() {
+        return Err(ParseError::MissingDevice(rest.to_string()));
+    }
+    let mut settings = serial::Settings::new(path);
+    for option in options.split('&').filter(|o| !o.is_empty()) {
+        match option.split_once('=') {
+            Some(("baud", baud)) => {
+                settings.baud = baud
+                    .parse()
+                    .map_err(|_| ParseError::Baud(baud.to_string()))?;
+            }
+            _ => return Err(ParseError::UnknownOption(option.to_string())),
+        }
+    }
+    Ok(settings)
 }
 
 pub enum Transmitter {
     Udp(udp::UdpTransmitter),
     Tcp(tcp::TcpTransmitter),
+    Serial(serial::SerialTransmitter),
 }
 
 impl Transmitter {
-    pub fn new(settings: Settings) -> Result<Self> {
+    /// Creates a transmitter, which counts the messages it fails to send in `failed_sends`.
+    /// Serial ports use the definitions of the deserializer to find packets in their stream.
+    pub fn new(
+        name: Name,
+        settings: Settings,
+        deserializer: Arc<mavlink::Deserializer>,
+        failed_sends: Arc<AtomicU64>,
+    ) -> Result<Self> {
         info!("Creating transmitter with settings: {:?}", settings);
         match settings {
-            Settings::Udp(settings) => udp::UdpTransmitter::new(settings).map(Self::Udp),
-            Settings::Tcp(settings) => tcp::TcpTransmitter::new(settings).map(Self::Tcp),
+            Settings::Udp(settings) => {
+                udp::UdpTransmitter::new(name, settings, failed_sends).map(Self::Udp)
+            }
+            Settings::Tcp(settings) => {
+                tcp::TcpTransmitter::new(name, settings, failed_sends).map(Self::Tcp)
+            }
+            Settings::Serial(settings) => {
+                serial::SerialTransmitter::new(name, settings, deserializer, failed_sends)
+                    .map(Self::Serial)
+            }
         }
     }
 
-    pub fn split(self) -> (Sender, Receiver) {
+    pub fn split(self) -> (Sender, Receiver, Tasks) {
         match self {
             Self::Udp(transmitter) => transmitter.split(),
             Self::Tcp(transmitter) => transmitter.split(),
+            Self::Serial(transmitter) => transmitter.split(),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_send_error_names_peer() {
+        let name: Name = "gcs".into();
+        let error = || std::io::Error::from(std::io::ErrorKind::BrokenPipe);
+        assert_eq!(
+            TransmitterError::Send(name.clone(), peer(([127, 0, 0, 1], 14550).into()), error())
+                .to_string(),
+            "[gcs] Failed to send message to 127.0.0.1:14550"
+        );
+        assert_eq!(
+            TransmitterError::Send(name, peer(serial::PEER), error()).to_string(),
+            "[gcs] Failed to send message"
+        );
+    }
+
+    #[test]
+    fn test_parse_url() {
+        assert_eq!(
+            "udp://127.0.0.1:14550".parse(),
+            Ok(Settings::Udp(udp::Settings {
+                address: ([127, 0, 0, 1], 14550).into()
+            }))
+        );
+        assert_eq!(
+            "tcp://:5760".parse(),
+            Ok(Settings::Tcp(tcp::Settings {
+                address: ([0, 0, 0, 0], 5760).into()
+            }))
+        );
+        assert_eq!(
+            "TCP://[::1]:5760"
+                .parse::<Settings>()
+                .map(|s| s.to_string()),
+            Ok("tcp://[::1]:5760".to_string())
+        );
+    }
+
+    #[test]
+    fn test_parse_serial_url() {
+        assert_eq!(
+            "serial:///dev/ttyACM0?baud=921600".parse(),
+            Ok(Settings::Serial(serial::Settings {
+                path: "/dev/ttyACM0".into(),
+                baud: 921600,
+            }))
+        );
+        assert_eq!(
+            "serial://COM3".parse::<Settings>().map(|s| s.to_string()),
+            Ok("serial://COM3?baud=57600".to_string())
+        );
+        assert_eq!(
+            "serial:///dev/ttyACM0?baud=fast".parse::<Settings>(),
+            Err(ParseError::Baud("fast".to_string()))
+        );
+        assert_eq!(
+            "serial:///dev/ttyACM0?parity=even".parse::<Settings>(),
+            Err(ParseError::UnknownOption("parity=even".to_string()))
+        );
+        assert!(matches!(
+            "serial://?baud=57600".parse::<Settings>(),
+            Err(ParseError::MissingDevice(_))
+        ));
+    }
+
+    #[test]
+    fn test_parse_invalid_url() {
+        assert!(matches!(
+            "0.0.0.0:14550".parse::<Settings>(),
+            Err(ParseError::MissingScheme(_))
+        ));
+        assert!(matches!(
+            "udp://localhost:14550".parse::<Settings>(),
+            Err(ParseError::Address(_))
+        ));
+        assert!(matches!(
+            "udp://:14550?broadcast=true".parse::<Settings>(),
+            Err(ParseError::Options(_))
+        ));
+    }
+}
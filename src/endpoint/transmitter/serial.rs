@@ -0,0 +1,269 @@
+use log::debug;
+use serde::{Deserialize, Serialize};
+use std::{
+    net::{Ipv4Addr, SocketAddr, SocketAddrV4},
+    path::PathBuf,
+    sync::{atomic::AtomicU64, Arc},
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt, ReadHalf, WriteHalf},
+    sync::mpsc,
+};
+use tokio_serial::{SerialPortBuilderExt, SerialStream};
+
+use super::{report_send, Data, Name, RecvError, RecvResult, Result, Tasks};
+use crate::mavlink::{self, v1, v2};
+
+/// The address of the device on the other end of a serial port, which has no address of its own.
+pub const PEER: SocketAddr = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 0));
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Settings {
+    /// The serial device, e.g. `/dev/ttyACM0`.
+    pub path: PathBuf,
+    #[serde(default = "default_baud")]
+    pub baud: u32,
+}
+
+fn default_baud() -> u32 {
+    57600
+}
+
+impl Settings {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            baud: default_baud(),
+        }
+    }
+}
+
+pub struct SerialTransmitter {
+    sender: super::Sender,
+    receiver: super::Receiver,
+    tasks: Tasks,
+}
+
+impl SerialTransmitter {
+    pub fn new(
+        name: Name,
+        settings: Settings,
+        deserializer: Arc<mavlink::Deserializer>,
+        failed_sends: Arc<AtomicU64>,
+    ) -> Result<Self> {
+        let channel_size = 16;
+
+        debug!(
+            "Opening serial port {} at {} baud",
+            settings.path.display(),
+            settings.baud
+        );
+        let port = tokio_serial::new(settings.path.to_string_lossy(), settings.baud)
+            .open_native_async()?;
+        let (reader, writer) = tokio::io::split(port);
+
+        let (msg_tx, receiver) = mpsc::channel(channel_size);
+        let receiver_task = tokio::spawn(recv(reader, Framer::new(deserializer), msg_tx));
+        let (sender, msg_rx) = mpsc::channel(channel_size);
+        let sender_task = tokio::spawn(write(name, writer, failed_sends, msg_rx));
+
+        Ok(Self {
+            sender,
+            receiver,
+            tasks: Tasks {
+                receiving: vec![receiver_task],
+                sending: vec![sender_task],
+            },
+        })
+    }
+
+    pub fn split(self) -> (super::Sender, super::Receiver, Tasks) {
+        (self.sender, self.receiver, self.tasks)
+    }
+}
+
+async fn recv(
+    mut reader: ReadHalf<SerialStream>,
+    mut framer: Framer,
+    msg_tx: mpsc::Sender<RecvResult>,
+) {
+    let mut buf = [0; 1024];
+    loop {
+        let res = tokio::select! {
+            res = reader.read(&mut buf) => res,
+            _ = msg_tx.closed() => return,
+        };
+        match res {
+            Ok(0) => {
+                debug!("Serial port closed");
+                return;
+            }
+            Ok(n) => {
+                framer.push(&buf[..n]);
+                while let Some(packet) = framer.next_packet() {
+                    if msg_tx.send(Ok((packet.into(), PEER))).await.is_err() {
+                        return;
+                    }
+                }
+            }
+            Err(e) => {
+                // The device is most likely gone, so reading again would fail right away
+                let _ = msg_tx
+                    .send(Err(RecvError {
+                        peer: None,
+                        error: e,
+                    }))
+                    .await;
+                return;
+            }
+        }
+    }
+}
+
+async fn write(
+    name: Name,
+    mut writer: WriteHalf<SerialStream>,
+    failed_sends: Arc<AtomicU64>,
+    mut msg_rx: mpsc::Receiver<Data>,
+) {
+    // Everything behind the port shares its address, so the target is irrelevant
+    while let Some((msg, _)) = msg_rx.recv().await {
+        let res = writer.write_all(&msg).await;
+        report_send(&name, None, &failed_sends, res);
+    }
+}
+
+/// Splits the byte stream of a serial port into MAVLink packets.
+struct Framer {
+    buf: Vec<u8>,
+    deserializer: Arc<mavlink::Deserializer>,
+}
+
+impl Framer {
+    fn new(deserializer: Arc<mavlink::Deserializer>) -> Self {
+        Self {
+            buf: Vec::new(),
+            deserializer,
+        }
+    }
+
+    fn push(&mut self, data: &[u8]) {
+        self.buf.extend_from_slice(data);
+    }
+
+    // Returns the next complete packet, skipping anything before its start. A magic byte in
+    // noise looks like the start of a packet too, which its checksum gives away.
+    fn next_packet(&mut self) -> Option<Vec<u8>> {
+        loop {
+            let start = self
+                .buf
+                .iter()
+                .position(|&b| b == v1::PACKET_MAGIC || b == v2::PACKET_MAGIC);
+            match start {
+                Some(start) => drop(self.buf.drain(..start)),
+                None => {
+                    self.buf.clear();
+                    return None;
+                }
+            }
+
+            let len = packet_len(&self.buf)?;
+            if self.buf.len() < len {
+                return None;
+            }
+            if self.deserializer.has_valid_checksum(&self.buf[..len]) {
+                return Some(self.buf.drain(..len).collect());
+            }
+            self.buf.remove(0);
+        }
+    }
+}
+
+// The length of the packet at the start of the buffer, if enough of its header is there.
+fn packet_len(buf: &[u8]) -> Option<usize> {
+    let payload_len = *buf.get(1)? as usize;
+    if buf[0] == v1::PACKET_MAGIC {
+        return Some(v1::HEADER_LEN + payload_len + v1::CHECKSUM_LEN);
+    }
+    let signature_len = if buf.get(2)? & v2::IFLAG_SIGNED != 0 {
+        v2::SIGNATURE_LEN
+    } else {
+        0
+    };
+    Some(v2::HEADER_LEN + payload_len + v2::CHECKSUM_LEN + signature_len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mavlink::definitions::Definitions;
+    use std::collections::HashMap;
+
+    // Knows the CRC_EXTRA of HEARTBEAT only, the checksums of other messages aren't checked.
+    fn framer() -> Framer {
+        Framer::new(Arc::new(mavlink::Deserializer::new(Definitions {
+            crc_extras: HashMap::from([(0, 50)]),
+            ..Default::default()
+        })))
+    }
+
+    fn packet_v2(payload_len: u8) -> Vec<u8> {
+        let mut packet = vec![v2::PACKET_MAGIC, payload_len];
+        packet.resize(v2::HEADER_LEN + payload_len as usize + v2::CHECKSUM_LEN, 0);
+        // An ID without CRC_EXTRA
+        packet[7] = 200;
+        packet
+    }
+
+    #[test]
+    fn test_framer_splits_stream_into_packets() {
+        let mut framer = framer();
+        let (first, second) = (packet_v2(9), packet_v2(3));
+        let stream: Vec<u8> = [&[0x42, 0x00][..], &first, &second].concat();
+
+        // Packets arrive in arbitrary chunks
+        framer.push(&stream[..5]);
+        assert_eq!(framer.next_packet(), None);
+        framer.push(&stream[5..]);
+        assert_eq!(framer.next_packet(), Some(first));
+        assert_eq!(framer.next_packet(), Some(second));
+        assert_eq!(framer.next_packet(), None);
+    }
+
+    #[test]
+    fn test_framer_handles_v1_and_signed_packets() {
+        let mut framer = framer();
+        let mut v1_packet = vec![v1::PACKET_MAGIC, 2];
+        v1_packet.resize(v1::HEADER_LEN + 2 + v1::CHECKSUM_LEN, 0);
+        v1_packet[5] = 200;
+        let mut signed = packet_v2(1);
+        signed[2] = v2::IFLAG_SIGNED;
+        signed.extend_from_slice(&[0; v2::SIGNATURE_LEN]);
+
+        framer.push(&[&v1_packet[..], &signed].concat());
+        assert_eq!(framer.next_packet(), Some(v1_packet));
+        assert_eq!(framer.next_packet(), Some(signed));
+    }
+
+    #[test]
+    fn test_framer_skips_spurious_magic_byte() {
+        let mut framer = framer();
+        let heartbeat = mavlink::serialize_v2(
+            mavlink::RoutingInfo {
+                sender: (1, 1).into(),
+                target: (0, 0).into(),
+            },
+            0,
+            0,
+            &[1; 9],
+            50,
+        )
+        .data
+        .to_vec();
+
+        // Noise which looks like the start of a short MAVLink 1 packet overlapping the heartbeat
+        framer.push(&[&[v1::PACKET_MAGIC, 3][..], &heartbeat].concat());
+        assert_eq!(framer.next_packet(), Some(heartbeat));
+        assert_eq!(framer.next_packet(), None);
+    }
+}
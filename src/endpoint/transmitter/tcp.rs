@@ -1,5 +1,9 @@
 use log::debug;
-use std::{collections::HashMap, net::SocketAddr, sync::Arc};
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{atomic::AtomicU64, Arc},
+};
 use tokio::{
     io::{AsyncReadExt, AsyncWriteExt},
     net::{
@@ -7,9 +11,10 @@ use tokio::{
         TcpListener,
     },
     sync::{mpsc, Mutex},
+    task::JoinHandle,
 };
 
-use super::{Data, RecvResult, Result};
+use super::{report_send, Data, Name, RecvError, RecvResult, Result, Tasks, TransmitterError};
 use crate::log_error::LogError;
 
 type Connections = Arc<Mutex<HashMap<SocketAddr, OwnedWriteHalf>>>;
@@ -22,10 +27,11 @@ pub struct Settings {
 pub struct TcpTransmitter {
     sender: super::Sender,
     receiver: super::Receiver,
+    tasks: Tasks,
 }
 
 impl TcpTransmitter {
-    pub fn new(settings: Settings) -> Result<Self> {
+    pub fn new(name: Name, settings: Settings, failed_sends: Arc<AtomicU64>) -> Result<Self> {
         let channel_size = 16;
         let addr = settings.address;
 
@@ -38,35 +44,49 @@ impl TcpTransmitter {
         let connections: Connections = Arc::new(Mutex::new(HashMap::new()));
 
         // Spawn tasks to accept connections and send messages, with corresponding channels
-        let receiver = start_acceptor_task(listener, connections.clone(), channel_size);
-        let sender = start_sender_task(connections, channel_size);
-
-        Ok(Self { sender, receiver })
+        let (receiver, acceptor_task) =
+            start_acceptor_task(listener, connections.clone(), channel_size);
+        let (sender, sender_task) =
+            start_sender_task(name, connections, failed_sends, channel_size);
+
+        Ok(Self {
+            sender,
+            receiver,
+            tasks: Tasks {
+                receiving: vec![acceptor_task],
+                sending: vec![sender_task],
+            },
+        })
     }
 
-    pub fn split(self) -> (super::Sender, super::Receiver) {
-        (self.sender, self.receiver)
+    pub fn split(self) -> (super::Sender, super::Receiver, Tasks) {
+        (self.sender, self.receiver, self.tasks)
     }
 }
 
-fn start_sender_task(connections: Connections, channel_size: usize) -> super::Sender {
+fn start_sender_task(
+    name: Name,
+    connections: Connections,
+    failed_sends: Arc<AtomicU64>,
+    channel_size: usize,
+) -> (super::Sender, JoinHandle<()>) {
     let (tx, rx) = mpsc::channel(channel_size);
-    tokio::spawn(async move {
-        write(rx, connections).await;
+    let task = tokio::spawn(async move {
+        write(name, rx, connections, failed_sends).await;
     });
-    tx
+    (tx, task)
 }
 
 fn start_acceptor_task(
     listener: TcpListener,
     connections: Connections,
     channel_size: usize,
-) -> super::Receiver {
+) -> (super::Receiver, JoinHandle<()>) {
     let (tx, rx) = mpsc::channel(channel_size);
-    tokio::spawn(async move {
+    let task = tokio::spawn(async move {
         accept_connections(listener, tx, connections).await;
     });
-    rx
+    (rx, task)
 }
 
 fn start_receiver_task(
@@ -93,7 +113,7 @@ async fn accept_connections(
                 continue;
             }
         };
-        debug!("Accepted connection from {}", addr);
+        debug!(peer:% = addr; "Accepted connection from {}", addr);
 
         let (reader, writer) = stream.into_split();
 
@@ -113,14 +133,25 @@ async fn recv(
 ) {
     let mut buf = [0; 65535];
     loop {
-        match reader.read(&mut buf).await {
+        let res = tokio::select! {
+            res = reader.read(&mut buf) => res,
+            // The endpoint stopped receiving, the connection is closed once sending is done
+            _ = msg_tx.closed() => return,
+        };
+        match res {
             Ok(0) => {
-                debug!("Connection closed by peer {}", addr);
+                debug!(peer:% = addr; "Connection closed by peer {}", addr);
                 break;
             }
             res => {
                 if msg_tx
-                    .send(res.map(|n| (buf[..n].to_vec().into(), addr)))
+                    .send(
+                        res.map(|n| (buf[..n].to_vec().into(), addr))
+                            .map_err(|error| RecvError {
+                                peer: Some(addr),
+                                error,
+                            }),
+                    )
                     .await
                     .log_error()
                     .is_none()
@@ -133,7 +164,12 @@ async fn recv(
     connections.lock().await.remove(&addr);
 }
 
-async fn write(mut msg_rx: mpsc::Receiver<Data>, connections: Connections) {
+async fn write(
+    name: Name,
+    mut msg_rx: mpsc::Receiver<Data>,
+    connections: Connections,
+    failed_sends: Arc<AtomicU64>,
+) {
     loop {
         let (msg, addr) = match msg_rx.recv().await {
             Some(msg) => msg,
@@ -144,10 +180,21 @@ async fn write(mut msg_rx: mpsc::Receiver<Data>, connections: Connections) {
         let writer = match connections.get_mut(&addr) {
             Some(writer) => writer,
             None => {
-                debug!("No connection to {}", addr);
+                debug!(peer:% = addr; "No connection to {}", addr);
                 continue;
             }
         };
-        writer.write_all(&msg).await.log_error();
+        let res = writer.write_all(&msg).await;
+        report_send(&name, Some(addr), &failed_sends, res);
+    }
+
+    // Everything has been sent, so close the connections cleanly
+    for (addr, mut writer) in connections.lock().await.drain() {
+        debug!(peer:% = addr; "Closing connection to {}", addr);
+        writer
+            .shutdown()
+            .await
+            .map_err(|e| TransmitterError::Close(name.clone(), addr, e))
+            .log_error_on(&name, Some(addr));
     }
 }
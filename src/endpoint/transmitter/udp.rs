@@ -2,13 +2,13 @@ use log::debug;
 use serde::{Deserialize, Serialize};
 use std::{
     net::{IpAddr, Ipv4Addr, SocketAddr},
-    sync::Arc,
+    sync::{atomic::AtomicU64, Arc},
 };
-use tokio::{net::UdpSocket, sync::mpsc};
+use tokio::{net::UdpSocket, sync::mpsc, task::JoinHandle};
 
 use crate::log_error::LogError;
 
-use super::{RecvResult, Result};
+use super::{report_send, Name, RecvError, RecvResult, Result, Tasks};
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Settings {
@@ -19,10 +19,11 @@ pub struct Settings {
 pub struct UdpTransmitter {
     sender: super::Sender,
     receiver: super::Receiver,
+    tasks: Tasks,
 }
 
 impl UdpTransmitter {
-    pub fn new(settings: Settings) -> Result<Self> {
+    pub fn new(name: Name, settings: Settings, failed_sends: Arc<AtomicU64>) -> Result<Self> {
         let channel_size = 16;
         let addr = settings.address;
 
@@ -41,33 +42,48 @@ impl UdpTransmitter {
         let socket = Arc::new(UdpSocket::from_std(socket)?);
 
         // Spawn tasks to send and receive messages, with corresponding channels
-        let receiver = start_receiver_task(socket.clone(), channel_size);
-        let sender = start_sender_task(socket, channel_size);
+        let (receiver, receiver_task) = start_receiver_task(socket.clone(), channel_size);
+        let (sender, sender_task) = start_sender_task(name, socket, failed_sends, channel_size);
 
-        Ok(Self { sender, receiver })
+        Ok(Self {
+            sender,
+            receiver,
+            tasks: Tasks {
+                receiving: vec![receiver_task],
+                sending: vec![sender_task],
+            },
+        })
     }
 
-    pub fn split(self) -> (super::Sender, super::Receiver) {
-        (self.sender, self.receiver)
+    pub fn split(self) -> (super::Sender, super::Receiver, Tasks) {
+        (self.sender, self.receiver, self.tasks)
     }
 }
 
-fn start_sender_task(socket: Arc<UdpSocket>, channel_size: usize) -> super::Sender {
+fn start_sender_task(
+    name: Name,
+    socket: Arc<UdpSocket>,
+    failed_sends: Arc<AtomicU64>,
+    channel_size: usize,
+) -> (super::Sender, JoinHandle<()>) {
     // Spawn a task to send messages
     let (tx, rx) = mpsc::channel(channel_size);
-    tokio::spawn(async move {
-        send(socket, rx).await;
+    let task = tokio::spawn(async move {
+        send(name, socket, failed_sends, rx).await;
     });
-    tx
+    (tx, task)
 }
 
-fn start_receiver_task(socket: Arc<UdpSocket>, channel_size: usize) -> super::Receiver {
+fn start_receiver_task(
+    socket: Arc<UdpSocket>,
+    channel_size: usize,
+) -> (super::Receiver, JoinHandle<()>) {
     // Spawn a task to receive messages
     let (tx, rx) = mpsc::channel(channel_size);
-    tokio::spawn(async move {
+    let task = tokio::spawn(async move {
         recv(socket, tx).await;
     });
-    rx
+    (rx, task)
 }
 
 async fn recv(socket: Arc<UdpSocket>, tx: mpsc::Sender<RecvResult>) {
@@ -76,7 +92,8 @@ async fn recv(socket: Arc<UdpSocket>, tx: mpsc::Sender<RecvResult>) {
         let data = socket
             .recv_from(&mut buf)
             .await
-            .map(|(amt, addr)| (buf[..amt].to_vec().into(), addr));
+            .map(|(amt, addr)| (buf[..amt].to_vec().into(), addr))
+            .map_err(|error| RecvError { peer: None, error });
         if tx.send(data).await.log_error().is_none() {
             // The receiver has been dropped
             break;
@@ -84,8 +101,14 @@ async fn recv(socket: Arc<UdpSocket>, tx: mpsc::Sender<RecvResult>) {
     }
 }
 
-async fn send(socket: Arc<UdpSocket>, mut rx: mpsc::Receiver<(Arc<[u8]>, SocketAddr)>) {
+async fn send(
+    name: Name,
+    socket: Arc<UdpSocket>,
+    failed_sends: Arc<AtomicU64>,
+    mut rx: mpsc::Receiver<(Arc<[u8]>, SocketAddr)>,
+) {
     while let Some((msg, target)) = rx.recv().await {
-        socket.send_to(&msg, target).await.log_error();
+        let res = socket.send_to(&msg, target).await.map(drop);
+        report_send(&name, Some(target), &failed_sends, res);
     }
 }
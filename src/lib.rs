@@ -1,60 +1,156 @@
 use anyhow::Result;
 use log::info;
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 
-use endpoint::{Endpoint, EndpointSettings};
+use endpoint::Endpoint;
+use supervisor::{ManagedEndpoint, Supervisor};
 
+pub use endpoint::{EndpointSettings, LinkStats, SenderStats};
+pub use supervisor::Handle;
+
+pub mod check;
 pub mod config;
+pub mod control;
 mod endpoint;
 mod log_error;
+pub mod logging;
 pub mod mavlink;
+mod metrics;
+pub mod reload;
 mod router;
+mod supervisor;
+#[cfg(unix)]
+pub mod systemd;
 
 fn endpoints_from_settings(
-    settings: Vec<EndpointSettings>,
+    settings: &[EndpointSettings],
     router: &mut router::Router,
     deserializer: Arc<mavlink::Deserializer>,
-) -> Result<Vec<Endpoint>> {
+    id_locks: &[endpoint::target_database::IdLock],
+) -> Result<Vec<(EndpointSettings, Endpoint)>> {
     settings
-        .into_iter()
+        .iter()
         .map(|settings| {
-            let (endpoint_tx, endpoint) =
-                Endpoint::from_settings(settings, router.tx(), deserializer.clone())?;
+            let (endpoint_tx, endpoint) = Endpoint::from_settings(
+                settings.clone(),
+                router.tx(),
+                deserializer.clone(),
+                id_locks,
+            )?;
 
-            router.add_endpoint(endpoint_tx);
-            Ok(endpoint)
+            router.add_endpoint(
+                endpoint.name().clone(),
+                endpoint_tx,
+                endpoint.link_stats().clone(),
+                settings.groups.clone(),
+            );
+            Ok((settings.clone(), endpoint))
         })
         .collect()
 }
 
 pub struct MAVLinkShouter {
+    settings: config::Settings,
     router: router::Router,
-    endpoints: Vec<Endpoint>,
+    endpoints: Vec<(EndpointSettings, Endpoint)>,
+    deserializer: Arc<mavlink::Deserializer>,
+    link_stats_interval: Option<Duration>,
+    metrics: Arc<metrics::Metrics>,
+    metrics_server: Option<metrics::Server>,
+    #[cfg(unix)]
+    control_server: Option<control::Server>,
 }
 
 impl MAVLinkShouter {
     pub fn new(settings: config::Settings) -> Result<Self> {
-        // Load the message offsets from the XML definitions
-        let deserializer = mavlink::definitions::try_get_offsets_from_xml(settings.definitions)
-            .inspect(|offsets| info!("Found {} targeted messages.", offsets.len()))
-            .map(mavlink::Deserializer::new)
-            .map(Arc::new)?;
+        // Load the message offsets and checksum seeds from the XML definitions
+        let deserializer =
+            mavlink::definitions::try_get_definitions_from_xml(settings.definitions.clone())
+                .inspect(|d| info!("Found {} targeted messages.", d.offsets.len()))
+                .map(mavlink::Deserializer::new)
+                .map(Arc::new)?;
 
-        let mut router = router::Router::default();
+        let mut router = router::Router::new(&settings);
+        let metrics = Arc::new(metrics::Metrics::new(router.metrics()));
+        let metrics_server = settings
+            .metrics
+            .as_ref()
+            .map(metrics::Server::bind)
+            .transpose()?;
+        #[cfg(unix)]
+        let control_server = settings
+            .control
+            .as_ref()
+            .map(control::Server::bind)
+            .transpose()?;
+        #[cfg(not(unix))]
+        if settings.control.is_some() {
+            log::warn!("The control API is only available on Unix");
+        }
 
         info!("Creating endpoints...");
-        let endpoints = endpoints_from_settings(settings.endpoints, &mut router, deserializer)?;
+        let endpoints = endpoints_from_settings(
+            &settings.endpoints,
+            &mut router,
+            deserializer.clone(),
+            &settings.id_locks,
+        )?;
+
+        Ok(Self {
+            link_stats_interval: settings
+                .link_stats_interval_ms
+                .filter(|&ms| ms > 0)
+                .map(Duration::from_millis),
+            settings,
+            router,
+            endpoints,
+            deserializer,
+            metrics,
+            metrics_server,
+            #[cfg(unix)]
+            control_server,
+        })
+    }
 
-        Ok(Self { router, endpoints })
+    /// The link statistics of every endpoint, which are updated while running.
+    pub fn link_stats(&self) -> Vec<Arc<LinkStats>> {
+        self.endpoints
+            .iter()
+            .map(|(_, endpoint)| endpoint.link_stats().clone())
+            .collect()
     }
 
-    pub fn run(self) {
+    pub fn run(self) -> Handle {
         info!("Starting endpoints...");
-        for endpoint in self.endpoints {
-            endpoint.start();
+        let endpoints = self
+            .endpoints
+            .into_iter()
+            .map(|(settings, endpoint)| {
+                self.metrics.add_endpoint(endpoint.metrics().clone());
+                ManagedEndpoint::start(settings, endpoint)
+            })
+            .collect();
+        if let Some(server) = self.metrics_server {
+            server.start(self.metrics.clone());
         }
+        let supervisor = Supervisor::new(
+            self.settings,
+            endpoints,
+            &self.router,
+            self.deserializer,
+            self.link_stats_interval,
+            self.metrics,
+        );
+
+        log_error::report_suppressed_errors();
 
         info!("Starting router...");
         self.router.start();
+        let handle = supervisor.start();
+        #[cfg(unix)]
+        if let Some(server) = self.control_server {
+            server.start(handle.clone());
+        }
+        handle
     }
 }
@@ -1,21 +1,307 @@
 use log::error;
+use parking_lot::{const_mutex, Mutex};
+use std::{
+    collections::BTreeMap,
+    net::SocketAddr,
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+use tokio::time::Instant;
+
+// A disconnected peer or a stream of bad packets can cause thousands of errors per second.
+pub const ERROR_LOG_INTERVAL: Duration = Duration::from_secs(10);
+
+// Errors are aggregated by the endpoint they occurred on and their message.
+static SUPPRESSED_ERRORS: Mutex<SuppressedErrors> = const_mutex(SuppressedErrors::new());
 
 pub trait LogError<T> {
+    /// Logs the error, at most once per interval for errors with the same endpoint and message.
     fn log_error(self) -> Option<T>;
 
-    fn log(e: &impl std::error::Error) {
-        let mut msg = e.to_string();
-        let mut e = e as &dyn std::error::Error;
-        while let Some(source) = e.source() {
-            msg += &format!(" caused by: {}", source);
-            e = source;
-        }
-        error!("{}", msg);
-    }
+    /// Like `log_error`, passing the endpoint and peer the error occurred on as fields.
+    fn log_error_on(self, endpoint: &str, peer: Option<SocketAddr>) -> Option<T>;
 }
 
 impl<T, E: std::error::Error> LogError<T> for Result<T, E> {
     fn log_error(self) -> Option<T> {
-        self.inspect_err(Self::log).ok()
+        self.inspect_err(|e| log_rate_limited(e, None, None)).ok()
+    }
+
+    fn log_error_on(self, endpoint: &str, peer: Option<SocketAddr>) -> Option<T> {
+        self.inspect_err(|e| log_rate_limited(e, Some(endpoint), peer))
+            .ok()
+    }
+}
+
+fn log_rate_limited(e: &impl std::error::Error, endpoint: Option<&str>, peer: Option<SocketAddr>) {
+    let key = (endpoint.map(str::to_string), e.to_string());
+    let suppressed = SUPPRESSED_ERRORS
+        .lock()
+        .should_log(key, peer, Instant::now());
+    if let Some(suppressed) = suppressed {
+        log_chain(e, suppressed, endpoint, peer);
+    }
+}
+
+fn log_chain(
+    e: &impl std::error::Error,
+    suppressed: u64,
+    endpoint: Option<&str>,
+    peer: Option<SocketAddr>,
+) {
+    let mut msg = e.to_string();
+    let mut causes = Vec::new();
+    let mut source = e.source();
+    while let Some(cause) = source {
+        msg += &format!(" caused by: {}", cause);
+        causes.push(cause.to_string());
+        source = cause.source();
+    }
+    if suppressed > 0 {
+        msg += &format!(" (suppressed {} similar errors)", suppressed);
+    }
+    // Also passed as fields, so structured output can keep the chain apart
+    let peer = peer.map(|peer| peer.to_string());
+    error!(
+        error:% = e,
+        causes = causes.join("\n").as_str(),
+        suppressed,
+        endpoint,
+        peer = peer.as_deref();
+        "{}", msg
+    );
+}
+
+// Whether a task reports the suppressed errors, of which there must be only one since they are
+// shared by all routers of the process.
+static REPORTING: AtomicBool = AtomicBool::new(false);
+
+// Allows reporting to start again once the task is gone, e.g. with the runtime it ran on.
+struct Reporting;
+
+impl Drop for Reporting {
+    fn drop(&mut self) {
+        REPORTING.store(false, Ordering::Release);
+    }
+}
+
+/// Starts periodically logging how many errors were suppressed, for errors which stopped
+/// occurring, unless this already happens.
+pub fn report_suppressed_errors() {
+    if REPORTING.swap(true, Ordering::AcqRel) {
+        return;
+    }
+    let reporting = Reporting;
+    tokio::spawn(async move {
+        let _reporting = reporting;
+        report().await
+    });
+}
+
+async fn report() {
+    // Checked more often than the interval, so summaries follow it closely
+    let mut interval = tokio::time::interval(Duration::from_secs(1));
+    loop {
+        let now = interval.tick().await;
+        for summary in SUPPRESSED_ERRORS.lock().flush(now) {
+            let peer = summary.peer.map(|peer| peer.to_string());
+            error!(
+                suppressed = summary.suppressed,
+                endpoint = summary.endpoint.as_deref(),
+                peer = peer.as_deref();
+                "{}: suppressed {} similar errors", summary.msg, summary.suppressed
+            );
+        }
+    }
+}
+
+// Logs at most once per interval, counting the messages which were not logged in between.
+pub struct SuppressedLog {
+    interval: Duration,
+    last_logged: Option<Instant>,
+    suppressed: u64,
+}
+
+impl SuppressedLog {
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            last_logged: None,
+            suppressed: 0,
+        }
+    }
+
+    fn is_due(&self, now: Instant) -> bool {
+        self.last_logged
+            .is_none_or(|t| now.duration_since(t) >= self.interval)
+    }
+
+    // Returns the number of suppressed messages if it is time to log again.
+    pub fn should_log(&mut self, now: Instant) -> Option<u64> {
+        if !self.is_due(now) {
+            self.suppressed += 1;
+            return None;
+        }
+        self.last_logged = Some(now);
+        Some(std::mem::take(&mut self.suppressed))
+    }
+}
+
+// The endpoint an error occurred on, if any, and its message.
+type ErrorKey = (Option<String>, String);
+
+// A suppressed log for each kind of error, with the peer it last occurred on.
+struct SuppressedErrors(BTreeMap<ErrorKey, (SuppressedLog, Option<SocketAddr>)>);
+
+// How often an error was suppressed since it was last logged.
+#[derive(Debug, PartialEq)]
+struct Summary {
+    endpoint: Option<String>,
+    msg: String,
+    peer: Option<SocketAddr>,
+    suppressed: u64,
+}
+
+impl SuppressedErrors {
+    const fn new() -> Self {
+        Self(BTreeMap::new())
+    }
+
+    fn should_log(&mut self, key: ErrorKey, peer: Option<SocketAddr>, now: Instant) -> Option<u64> {
+        let (log, last_peer) = self
+            .0
+            .entry(key)
+            .or_insert_with(|| (SuppressedLog::new(ERROR_LOG_INTERVAL), None));
+        *last_peer = peer;
+        log.should_log(now)
+    }
+
+    // Returns the errors which were suppressed but are due to be logged again, and forgets the
+    // ones which stopped occurring.
+    fn flush(&mut self, now: Instant) -> Vec<Summary> {
+        let mut flushed = Vec::new();
+        self.0.retain(|(endpoint, msg), (log, peer)| {
+            if !log.is_due(now) {
+                return true;
+            }
+            if log.suppressed == 0 {
+                return false;
+            }
+            flushed.push(Summary {
+                endpoint: endpoint.clone(),
+                msg: msg.clone(),
+                peer: *peer,
+                suppressed: std::mem::take(&mut log.suppressed),
+            });
+            log.last_logged = Some(now);
+            true
+        });
+        flushed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reporting_stops_with_its_runtime() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .unwrap();
+        runtime.block_on(async {
+            report_suppressed_errors();
+            assert!(REPORTING.load(Ordering::Acquire));
+        });
+        // Another runtime can report again once the first one is gone
+        drop(runtime);
+        assert!(!REPORTING.load(Ordering::Acquire));
+    }
+
+    #[test]
+    fn test_suppressed_log_counts_messages_between_logs() {
+        let mut log = SuppressedLog::new(ERROR_LOG_INTERVAL);
+        let now = Instant::now();
+
+        assert_eq!(log.should_log(now), Some(0));
+        assert_eq!(log.should_log(now + Duration::from_secs(1)), None);
+        assert_eq!(log.should_log(now + Duration::from_secs(2)), None);
+        assert_eq!(log.should_log(now + ERROR_LOG_INTERVAL), Some(2));
+    }
+
+    fn key(endpoint: Option<&str>, msg: &str) -> ErrorKey {
+        (endpoint.map(str::to_string), msg.to_string())
+    }
+
+    fn summary(endpoint: Option<&str>, msg: &str, suppressed: u64) -> Summary {
+        Summary {
+            endpoint: endpoint.map(str::to_string),
+            msg: msg.to_string(),
+            peer: None,
+            suppressed,
+        }
+    }
+
+    #[test]
+    fn test_suppressed_errors_are_aggregated_by_message() {
+        let mut errors = SuppressedErrors::new();
+        let now = Instant::now();
+        let later = now + Duration::from_secs(1);
+        let failed = || key(None, "Failed");
+
+        assert_eq!(errors.should_log(failed(), None, now), Some(0));
+        assert_eq!(errors.should_log(failed(), None, later), None);
+        assert_eq!(errors.should_log(failed(), None, later), None);
+        assert_eq!(errors.should_log(key(None, "Other"), None, later), Some(0));
+
+        // Only errors which were suppressed are summarized, the others are forgotten
+        let now = later + ERROR_LOG_INTERVAL;
+        assert_eq!(errors.flush(now), vec![summary(None, "Failed", 2)]);
+        assert_eq!(errors.0.len(), 1);
+        assert_eq!(errors.should_log(failed(), None, now), None);
+        assert_eq!(
+            errors.flush(now + ERROR_LOG_INTERVAL),
+            vec![summary(None, "Failed", 1)]
+        );
+        assert_eq!(errors.flush(now + ERROR_LOG_INTERVAL * 2), vec![]);
+        assert!(errors.0.is_empty());
+    }
+
+    #[test]
+    fn test_suppressed_errors_are_aggregated_per_endpoint() {
+        let mut errors = SuppressedErrors::new();
+        let now = Instant::now();
+        let later = now + Duration::from_secs(1);
+        let peer: SocketAddr = ([127, 0, 0, 1], 14550).into();
+
+        // The same error on another endpoint is logged as well
+        assert_eq!(
+            errors.should_log(key(Some("a"), "Closed"), None, now),
+            Some(0)
+        );
+        assert_eq!(
+            errors.should_log(key(Some("b"), "Closed"), None, now),
+            Some(0)
+        );
+        assert_eq!(
+            errors.should_log(key(Some("a"), "Closed"), None, later),
+            None
+        );
+        assert_eq!(
+            errors.should_log(key(Some("b"), "Closed"), Some(peer), later),
+            None
+        );
+
+        assert_eq!(
+            errors.flush(now + ERROR_LOG_INTERVAL),
+            vec![
+                summary(Some("a"), "Closed", 1),
+                Summary {
+                    peer: Some(peer),
+                    ..summary(Some("b"), "Closed", 1)
+                },
+            ]
+        );
     }
 }
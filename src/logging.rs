@@ -0,0 +1,143 @@
+use log::kv::{self, VisitSource, VisitValue};
+use serde_json::{Map, Value};
+use std::{fmt::Display, io::Write};
+
+// Collects the key-values of a record as strings, leaving out those which aren't set.
+struct Fields(Map<String, Value>);
+
+impl<'kvs> VisitSource<'kvs> for Fields {
+    fn visit_pair(&mut self, key: kv::Key<'kvs>, value: kv::Value<'kvs>) -> Result<(), kv::Error> {
+        let mut is_null = IsNull(false);
+        value.visit(&mut is_null)?;
+        if !is_null.0 {
+            self.0
+                .insert(key.to_string(), Value::String(value.to_string()));
+        }
+        Ok(())
+    }
+}
+
+struct IsNull(bool);
+
+impl<'v> VisitValue<'v> for IsNull {
+    fn visit_any(&mut self, _: kv::Value) -> Result<(), kv::Error> {
+        Ok(())
+    }
+
+    fn visit_null(&mut self) -> Result<(), kv::Error> {
+        self.0 = true;
+        Ok(())
+    }
+}
+
+fn json_record(record: &log::Record, timestamp: impl Display) -> Value {
+    let mut fields = Fields(Map::new());
+    // Visiting only fails if the visitor does
+    let _ = record.key_values().visit(&mut fields);
+    let mut fields = fields.0;
+
+    // Errors carry their chain of causes separately, see `LogError`
+    let message = match fields.remove("error") {
+        Some(Value::String(error)) => error,
+        _ => record.args().to_string(),
+    };
+    // The endpoint is a field of its own, so it needn't prefix the message as well
+    let message = match fields.get("endpoint") {
+        Some(Value::String(endpoint)) => message
+            .strip_prefix(&format!("[{}] ", endpoint))
+            .map(str::to_string)
+            .unwrap_or(message),
+        _ => message,
+    };
+    if let Some(Value::String(causes)) = fields.remove("causes") {
+        let causes = causes.lines().map(Value::from).collect();
+        fields.insert("causes".to_string(), Value::Array(causes));
+    }
+
+    fields.insert("timestamp".to_string(), timestamp.to_string().into());
+    fields.insert("level".to_string(), record.level().as_str().into());
+    fields.insert("message".to_string(), message.into());
+    Value::Object(fields)
+}
+
+/// Formats records as one JSON object per line, with the endpoint, peer address and causes of
+/// errors as separate fields.
+pub fn format_json(
+    buf: &mut env_logger::fmt::Formatter,
+    record: &log::Record,
+) -> std::io::Result<()> {
+    let timestamp = buf.timestamp_millis();
+    writeln!(buf, "{}", json_record(record, timestamp))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_json_record_fields() {
+        let kvs = [("endpoint", "gcs"), ("peer", "127.0.0.1:14550")];
+        let record = log::Record::builder()
+            .level(log::Level::Warn)
+            .args(format_args!("[gcs] Dropping message"))
+            .key_values(&kvs)
+            .build();
+
+        assert_eq!(
+            json_record(&record, "2024-01-01T00:00:00.000Z"),
+            json!({
+                "timestamp": "2024-01-01T00:00:00.000Z",
+                "level": "WARN",
+                "endpoint": "gcs",
+                "peer": "127.0.0.1:14550",
+                "message": "Dropping message",
+            })
+        );
+    }
+
+    #[test]
+    fn test_json_record_error_chain() {
+        let kvs = [
+            ("error", "[gcs] Failed to receive message"),
+            ("causes", "Connection refused\nos error 111"),
+            ("endpoint", "gcs"),
+        ];
+        let record = log::Record::builder()
+            .level(log::Level::Error)
+            .args(format_args!(
+                "[gcs] Failed to receive message caused by: ..."
+            ))
+            .key_values(&kvs)
+            .build();
+
+        let value = json_record(&record, "");
+        assert_eq!(value["endpoint"], "gcs");
+        assert_eq!(value["message"], "Failed to receive message");
+        assert_eq!(
+            value["causes"],
+            json!(["Connection refused", "os error 111"])
+        );
+    }
+
+    #[test]
+    fn test_json_record_without_endpoint() {
+        let kvs: [(&str, Option<&str>); 1] = [("peer", None)];
+        let record = log::Record::builder()
+            .level(log::Level::Warn)
+            .args(format_args!(
+                "[vehicle1] Switching from link 'radio' to 'lte'"
+            ))
+            .key_values(&kvs)
+            .build();
+
+        // Other names in brackets, like those of link groups, aren't taken for an endpoint
+        let value = json_record(&record, "");
+        assert_eq!(value.get("endpoint"), None);
+        assert_eq!(value.get("peer"), None);
+        assert_eq!(
+            value["message"],
+            "[vehicle1] Switching from link 'radio' to 'lte'"
+        );
+    }
+}
@@ -1,32 +1,313 @@
 use anyhow::Result;
-use clap::Parser;
-use std::path;
+use clap::{error::ErrorKind, CommandFactory, Parser, Subcommand, ValueEnum};
+use log::error;
+use std::{net::SocketAddr, path, process::ExitCode, time::Duration};
 
-use mavlink_shouter::{config, MAVLinkShouter};
+use mavlink_shouter::{check, config, control, logging, reload, EndpointSettings, MAVLinkShouter};
+#[cfg(unix)]
+use {
+    log::warn,
+    mavlink_shouter::{systemd, Handle},
+    std::sync::Arc,
+};
 
 #[derive(Debug, Parser)]
 #[command(author, version, about, long_about = None)]
 struct Args {
-    /// The configuration file to use.
-    #[arg(short, long, default_value = "config/default.yml")]
-    config: path::PathBuf,
+    /// The configuration file to use, required unless `--definitions` is given.
+    #[arg(short, long)]
+    config: Option<path::PathBuf>,
+    /// The XML definition file, overriding the one of the configuration file.
+    #[arg(short, long)]
+    definitions: Option<path::PathBuf>,
+    /// An endpoint like `gcs=udp://0.0.0.0:14550` or `tcp://:5760`, replacing the configured
+    /// endpoint of the same name.
+    #[arg(short, long = "endpoint", value_name = "[NAME=]URL")]
+    endpoints: Vec<EndpointSettings>,
+    /// Use only the endpoints given with `--endpoint`, ignoring the configured ones.
+    #[arg(long, requires = "endpoints")]
+    replace_endpoints: bool,
+    /// Reload the configuration when the file changes, it is always reloaded on SIGHUP.
+    #[arg(short, long, requires = "config")]
+    watch: bool,
+    /// How log messages are written to stderr.
+    #[arg(long, value_enum, default_value_t = LogFormat::Text)]
+    log_format: LogFormat,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum LogFormat {
+    Text,
+    /// One JSON object per line, for log aggregators.
+    Json,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Check the configuration without starting the router.
+    Check,
+    /// Inspect or steer a running router through its control socket.
+    Ctl {
+        /// The control socket, taken from the configuration if not set.
+        #[arg(short, long)]
+        socket: Option<path::PathBuf>,
+        #[command(subcommand)]
+        command: CtlCommand,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum CtlCommand {
+    /// List all endpoints with their state.
+    List,
+    /// List the learned and static routes of an endpoint.
+    Routes { endpoint: String },
+    /// Send messages for a component to an address of an endpoint.
+    AddRoute {
+        endpoint: String,
+        sys_id: u8,
+        comp_id: u8,
+        address: SocketAddr,
+    },
+    /// Remove a route added with `add-route`.
+    RemoveRoute {
+        endpoint: String,
+        sys_id: u8,
+        comp_id: u8,
+        address: SocketAddr,
+    },
+    /// Resume routing to and from an endpoint.
+    Enable { endpoint: String },
+    /// Stop routing to and from an endpoint, without closing its sockets.
+    Disable { endpoint: String },
+    /// Reset the link statistics of an endpoint, or of all endpoints.
+    ResetStats { endpoint: Option<String> },
+}
+
+impl From<CtlCommand> for control::Request {
+    fn from(command: CtlCommand) -> Self {
+        let route = |endpoint, sys_id, comp_id, address| control::Route {
+            endpoint,
+            sys_id,
+            comp_id,
+            address,
+        };
+        match command {
+            CtlCommand::List => Self::List,
+            CtlCommand::Routes { endpoint } => Self::Routes { endpoint },
+            CtlCommand::AddRoute {
+                endpoint,
+                sys_id,
+                comp_id,
+                address,
+            } => Self::AddRoute(route(endpoint, sys_id, comp_id, address)),
+            CtlCommand::RemoveRoute {
+                endpoint,
+                sys_id,
+                comp_id,
+                address,
+            } => Self::RemoveRoute(route(endpoint, sys_id, comp_id, address)),
+            CtlCommand::Enable { endpoint } => Self::Enable { endpoint },
+            CtlCommand::Disable { endpoint } => Self::Disable { endpoint },
+            CtlCommand::ResetStats { endpoint } => Self::ResetStats { endpoint },
+        }
+    }
+}
+
+#[cfg(unix)]
+async fn ctl(socket: &path::Path, command: CtlCommand) -> Result<ExitCode> {
+    match control::request(socket, &command.into()).await? {
+        control::Response::Endpoints(endpoints) => {
+            for e in endpoints {
+                println!(
+                    "{}: {} ({}), {} targets, received {}, lost {}, invalid senders {}",
+                    e.name,
+                    e.url,
+                    if e.enabled { "enabled" } else { "disabled" },
+                    e.targets,
+                    e.received,
+                    e.lost,
+                    e.invalid_senders
+                );
+            }
+        }
+        control::Response::Routes(routes) => {
+            for route in routes {
+                let last_seen = match route.last_seen_ms {
+                    Some(ms) => format!("last seen {} ms ago", ms),
+                    None => "static".to_string(),
+                };
+                println!(
+                    "{}:{} at {}, {}",
+                    route.sys_id, route.comp_id, route.address, last_seen
+                );
+            }
+        }
+        control::Response::Done => println!("Done."),
+        control::Response::Error(e) => {
+            eprintln!("error: {}", e);
+            return Ok(ExitCode::FAILURE);
+        }
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+#[cfg(not(unix))]
+async fn ctl(_: &path::Path, _: CtlCommand) -> Result<ExitCode> {
+    anyhow::bail!("The control API is only available on Unix")
+}
+
+fn check(settings: &config::Settings) -> ExitCode {
+    let report = check::check(settings);
+
+    println!("Endpoints:");
+    for endpoint in &settings.endpoints {
+        println!("  {}: {}", endpoint.name, endpoint.kind);
+    }
+    if let Some(targeted_messages) = report.targeted_messages {
+        println!("Found {} targeted messages.", targeted_messages);
+    }
+
+    if report.problems.is_empty() {
+        println!("The configuration is valid.");
+        return ExitCode::SUCCESS;
+    }
+    for problem in &report.problems {
+        let mut msg = problem.to_string();
+        let mut e: &dyn std::error::Error = problem;
+        while let Some(source) = e.source() {
+            msg += &format!(" caused by: {}", source);
+            e = source;
+        }
+        eprintln!("error: {}", msg);
+    }
+    ExitCode::FAILURE
+}
+
+// How long to wait for queued messages to be sent when shutting down.
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[cfg(unix)]
+async fn terminated() -> std::io::Result<()> {
+    use tokio::signal::unix::{signal, SignalKind};
+    let mut terminate = signal(SignalKind::terminate())?;
+    tokio::select! {
+        res = tokio::signal::ctrl_c() => res,
+        _ = terminate.recv() => Ok(()),
+    }
+}
+
+// There is no SIGTERM outside of Unix
+#[cfg(not(unix))]
+async fn terminated() -> std::io::Result<()> {
+    tokio::signal::ctrl_c().await
+}
+
+// Tells systemd the router is ready, and keeps resetting its watchdog while the router runs.
+#[cfg(unix)]
+async fn notify_systemd(handle: &Handle) -> Option<Arc<systemd::Notifier>> {
+    let notifier = match systemd::Notifier::from_env() {
+        Ok(notifier) => Arc::new(notifier?),
+        Err(e) => {
+            warn!("Failed to connect to the service manager: {}", e);
+            return None;
+        }
+    };
+    let endpoints = handle.ping(Duration::from_secs(1)).await.unwrap_or(0);
+    if let Err(e) = notifier.ready(endpoints) {
+        warn!("Failed to notify the service manager: {}", e);
+    }
+    if let Some(interval) = systemd::watchdog_interval() {
+        tokio::spawn(notifier.clone().watchdog(handle.clone(), interval));
+    }
+    Some(notifier)
+}
+
+// The configuration can be left out if the command line has everything the command needs, which
+// clap can't express since it depends on the subcommand.
+fn require_config(args: &Args) {
+    let needs_config = match &args.command {
+        Some(Command::Ctl { socket, .. }) => socket.is_none(),
+        _ => args.definitions.is_none(),
+    };
+    if needs_config && args.config.is_none() {
+        Args::command()
+            .error(
+                ErrorKind::MissingRequiredArgument,
+                "the following required arguments were not provided:\n  --config <CONFIG>",
+            )
+            .exit();
+    }
 }
 
 #[tokio::main]
-async fn main() -> Result<()> {
-    env_logger::builder()
+async fn main() -> Result<ExitCode> {
+    let args = Args::parse();
+    require_config(&args);
+    let mut logger = env_logger::builder();
+    logger
         .format_module_path(false)
         .format_target(false)
         .filter_level(log::LevelFilter::Info)
-        .parse_default_env()
-        .init();
+        .parse_default_env();
+    if let LogFormat::Json = args.log_format {
+        logger.format(logging::format_json);
+    }
+    logger.init();
 
-    let args = Args::parse();
-    let settings = config::Settings::load(&args.config)?;
+    let overrides = config::Overrides {
+        definitions: args.definitions,
+        endpoints: args.endpoints,
+        replace_endpoints: args.replace_endpoints,
+    };
+    if let Some(Command::Ctl {
+        socket: Some(socket),
+        command,
+    }) = args.command
+    {
+        return ctl(&socket, command).await;
+    }
+    let settings = overrides.load(args.config.as_deref())?;
+    match args.command {
+        Some(Command::Check) => return Ok(check(&settings)),
+        Some(Command::Ctl { command, .. }) => {
+            let socket = match &settings.control {
+                Some(control) => &control.path,
+                None => anyhow::bail!("The configuration has no control socket, use --socket"),
+            };
+            return ctl(socket, command).await;
+        }
+        None => {}
+    }
+
+    let handle = MAVLinkShouter::new(settings)?.run();
+    // Without a configuration file, there is nothing to reload
+    if let Some(config) = args.config {
+        let poll_interval = args.watch.then_some(Duration::from_secs(1));
+        let handle = handle.clone();
+        tokio::spawn(async move {
+            if let Err(e) = reload::watch(config, overrides, handle, poll_interval).await {
+                error!("Failed to watch the configuration: {}", e);
+            }
+        });
+    }
 
-    MAVLinkShouter::new(settings)?.run();
+    #[cfg(unix)]
+    let notifier = notify_systemd(&handle).await;
 
-    tokio::signal::ctrl_c().await?;
+    terminated().await?;
 
-    Ok(())
+    #[cfg(unix)]
+    if let Some(notifier) = notifier {
+        if let Err(e) = notifier.stopping() {
+            warn!("Failed to notify the service manager: {}", e);
+        }
+    }
+    if handle.shutdown(DRAIN_TIMEOUT).await {
+        Ok(ExitCode::SUCCESS)
+    } else {
+        Ok(ExitCode::FAILURE)
+    }
 }
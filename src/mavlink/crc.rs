@@ -0,0 +1,42 @@
+/// The CRC-16/MCRF4XX checksum used by MAVLink, also known as X.25.
+#[derive(Debug, Clone, Copy)]
+pub struct Crc(u16);
+
+impl Crc {
+    pub fn new() -> Self {
+        Self(0xFFFF)
+    }
+
+    pub fn accumulate(&mut self, data: &[u8]) {
+        for &byte in data {
+            let tmp = byte ^ (self.0 & 0xFF) as u8;
+            let tmp = tmp ^ (tmp << 4);
+            let tmp = tmp as u16;
+            self.0 = (self.0 >> 8) ^ (tmp << 8) ^ (tmp << 3) ^ (tmp >> 4);
+        }
+    }
+
+    pub fn value(&self) -> u16 {
+        self.0
+    }
+}
+
+/// Computes the checksum of a packet, covering everything after the magic byte.
+pub fn checksum(packet: &[u8], crc_extra: u8) -> u16 {
+    let mut crc = Crc::new();
+    crc.accumulate(packet.get(1..).unwrap_or_default());
+    crc.accumulate(&[crc_extra]);
+    crc.value()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crc_check_value() {
+        let mut crc = Crc::new();
+        crc.accumulate(b"123456789");
+        assert_eq!(crc.value(), 0x6F91);
+    }
+}
@@ -1,7 +1,8 @@
 use std::collections::HashMap;
 use std::path::PathBuf;
 
-use parser::{ParseError, Parser};
+pub use parser::ParseError;
+use parser::Parser;
 
 mod msg_parser;
 mod parser;
@@ -29,7 +30,15 @@ pub struct TargetedMessage {
 
 pub type ID = u32;
 
-pub fn try_get_offsets_from_xml(xml: PathBuf) -> Result<HashMap<ID, Offsets>, ParseError> {
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Definitions {
+    /// The offsets of the target fields of all targeted messages.
+    pub offsets: HashMap<ID, Offsets>,
+    /// The CRC_EXTRA of all messages, needed to compute their checksums.
+    pub crc_extras: HashMap<ID, u8>,
+}
+
+pub fn try_get_definitions_from_xml(xml: PathBuf) -> Result<Definitions, ParseError> {
     let mut parser = Parser::new();
     parser.parse_xml(xml)?;
 
@@ -41,5 +50,8 @@ pub fn try_get_offsets_from_xml(xml: PathBuf) -> Result<HashMap<ID, Offsets>, Pa
     if !has_unique_ids {
         return Err(ParseError::MultipleMessagesWithSameId);
     }
-    Ok(offsets)
+    Ok(Definitions {
+        offsets,
+        crc_extras: parser.crc_extras,
+    })
 }
@@ -6,6 +6,7 @@ use std::num::NonZeroUsize;
 use thiserror::Error;
 
 use super::Offsets;
+use crate::mavlink::crc::Crc;
 
 #[derive(Debug, Error)]
 pub enum MsgParseError {
@@ -54,17 +55,8 @@ enum MessageFieldKind {
 
 impl MessageFieldKind {
     fn from_str(p: &str) -> Result<(Self, NonZeroUsize), MsgParseError> {
-        let (s, n) = match p.find('[') {
-            Some(i) => {
-                let (s, n) = p.split_at(i);
-                let end = n.find(']').ok_or(MsgParseError::MalformedArraySize)?;
-                let n = n[1..end]
-                    .parse::<usize>()
-                    .map_err(|_| MsgParseError::FailedToParseArraySize(p.to_string()))?;
-                (s, NonZeroUsize::new(n).ok_or(MsgParseError::ZeroArraySize)?)
-            }
-            None => (p, NonZeroUsize::new(1).unwrap()),
-        };
+        let (s, n) = Self::split_array(p)?;
+        let n = n.unwrap_or(NonZeroUsize::new(1).unwrap());
         let kind = match s {
             "char" => Self::Char,
             "uint8_t" => Self::U8,
@@ -83,6 +75,24 @@ impl MessageFieldKind {
         Ok((kind, n))
     }
 
+    // Splits a type like 'uint8_t[3]' into its base type and array size.
+    fn split_array(p: &str) -> Result<(&str, Option<NonZeroUsize>), MsgParseError> {
+        match p.find('[') {
+            Some(i) => {
+                let (s, n) = p.split_at(i);
+                let end = n.find(']').ok_or(MsgParseError::MalformedArraySize)?;
+                let n = n[1..end]
+                    .parse::<usize>()
+                    .map_err(|_| MsgParseError::FailedToParseArraySize(p.to_string()))?;
+                Ok((
+                    s,
+                    Some(NonZeroUsize::new(n).ok_or(MsgParseError::ZeroArraySize)?),
+                ))
+            }
+            None => Ok((p, None)),
+        }
+    }
+
     fn size(&self) -> usize {
         match self {
             Self::Char => 1,
@@ -103,8 +113,11 @@ impl MessageFieldKind {
 #[derive(Debug, Clone)]
 struct MessageField {
     name: String,
+    // The C type as used for the CRC_EXTRA, without the array size.
+    c_type: String,
     kind: MessageFieldKind,
     multiplicity: NonZeroUsize,
+    is_array: bool,
 }
 
 impl MessageField {
@@ -118,10 +131,17 @@ impl MessageField {
             None => return Err(MsgParseError::FieldWithoutType),
         };
         let (kind, multiplicity) = MessageFieldKind::from_str(&field_type)?;
+        let (c_type, array_size) = MessageFieldKind::split_array(&field_type)?;
+        let c_type = match c_type {
+            "uint8_t_mavlink_version" => "uint8_t",
+            c_type => c_type,
+        };
         Ok(Self {
             name: name.to_string(),
+            c_type: c_type.to_string(),
             kind,
             multiplicity,
+            is_array: array_size.is_some(),
         })
     }
 
@@ -175,7 +195,20 @@ impl MsgParser {
         Ok(())
     }
 
-    fn compute_offsets(&mut self) -> Result<Option<Offsets>, MsgParseError> {
+    fn sort_fields(&mut self) {
+        // Sort the fields in decending order so that the extensions fields stay at the end and in the same
+        // order as in the XML.
+        let num_base_fields = self.num_base_fields();
+        let fields_to_sort = &mut self.msg_fields[..num_base_fields];
+        fields_to_sort.sort_by_key(|f| std::cmp::Reverse(f.kind.size()));
+    }
+
+    fn num_base_fields(&self) -> usize {
+        self.extensions_start_idx.unwrap_or(self.msg_fields.len())
+    }
+
+    // Expects the fields to be sorted already.
+    fn compute_offsets(&self) -> Result<Option<Offsets>, MsgParseError> {
         if !self.is_targeted_msg {
             return Ok(None);
         }
@@ -183,13 +216,6 @@ impl MsgParser {
         let mut system_offset = None;
         let mut component_offset = None;
 
-        // Sort the fields in decending order so that the extensions fields stay at the end and in the same
-        // order as in the XML.
-        let num_fields = self.msg_fields.len();
-        let fields_to_sort =
-            &mut self.msg_fields[..self.extensions_start_idx.unwrap_or(num_fields)];
-        fields_to_sort.sort_by(|a, b| b.kind.size().cmp(&a.kind.size()));
-
         self.msg_fields.iter().fold(0, |offset, field| {
             match field.name.as_str() {
                 "target_system" => system_offset = Some(offset),
@@ -211,11 +237,36 @@ impl MsgParser {
             (None, None) => Ok(None),
         }
     }
+
+    // Expects the fields to be sorted already. Extension fields are not part of the CRC_EXTRA.
+    fn compute_crc_extra(&self, msg_name: &str) -> u8 {
+        let mut crc = Crc::new();
+        crc.accumulate(msg_name.as_bytes());
+        crc.accumulate(b" ");
+        for field in &self.msg_fields[..self.num_base_fields()] {
+            crc.accumulate(field.c_type.as_bytes());
+            crc.accumulate(b" ");
+            crc.accumulate(field.name.as_bytes());
+            crc.accumulate(b" ");
+            if field.is_array {
+                crc.accumulate(&[field.multiplicity.get() as u8]);
+            }
+        }
+        let crc = crc.value();
+        (crc & 0xFF) as u8 ^ (crc >> 8) as u8
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParsedMessage {
+    pub offsets: Option<Offsets>,
+    pub crc_extra: u8,
 }
 
-pub fn try_get_offsets_from_msg(
+pub fn try_parse_msg(
+    msg_name: &str,
     reader: &mut Reader<&[u8]>,
-) -> Result<Option<Offsets>, MsgParseError> {
+) -> Result<ParsedMessage, MsgParseError> {
     let mut parser = MsgParser::new();
 
     loop {
@@ -228,7 +279,11 @@ pub fn try_get_offsets_from_msg(
                 parser.record_extension_start()?;
             }
             Event::End(ref f) if f.name().0 == b"message" => {
-                return parser.compute_offsets();
+                parser.sort_fields();
+                return Ok(ParsedMessage {
+                    offsets: parser.compute_offsets()?,
+                    crc_extra: parser.compute_crc_extra(msg_name),
+                });
             }
             Event::Eof => return Err(MsgParseError::UnexpectedEof),
             _ => {}
@@ -246,6 +301,72 @@ mod tests {
         reader
     }
 
+    fn try_get_offsets_from_msg(
+        reader: &mut Reader<&[u8]>,
+    ) -> Result<Option<Offsets>, MsgParseError> {
+        try_parse_msg("", reader).map(|msg| msg.offsets)
+    }
+
+    #[test]
+    fn test_try_parse_msg_crc_extra() -> Result<(), MsgParseError> {
+        let mut reader = reader_from_str(
+            r#"<message id="0" name="HEARTBEAT">
+                <field type="uint8_t" name="type" enum="MAV_TYPE">Vehicle or component type.</field>
+                <field type="uint8_t" name="autopilot" enum="MAV_AUTOPILOT">Autopilot type / class.</field>
+                <field type="uint8_t" name="base_mode" enum="MAV_MODE_FLAG" display="bitmask">System mode bitmap.</field>
+                <field type="uint32_t" name="custom_mode">A bitfield for use for autopilot-specific flags</field>
+                <field type="uint8_t" name="system_status" enum="MAV_STATE">System status flag.</field>
+                <field type="uint8_t_mavlink_version" name="mavlink_version">MAVLink version</field>
+            </message>"#,
+        );
+
+        let msg = try_parse_msg("HEARTBEAT", &mut reader)?;
+        assert_eq!(msg.crc_extra, 50);
+        assert_eq!(msg.offsets, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_try_parse_msg_crc_extra_with_arrays_and_extensions() -> Result<(), MsgParseError> {
+        let mut reader = reader_from_str(
+            r#"<message id="253" name="STATUSTEXT">
+                <field type="uint8_t" name="severity" enum="MAV_SEVERITY">Severity of status.</field>
+                <field type="char[50]" name="text">Status text message</field>
+                <extensions/>
+                <field type="uint16_t" name="id">Unique (opaque) identifier for this statustext message.</field>
+                <field type="uint8_t" name="chunk_seq">The chunk sequence number.</field>
+            </message>"#,
+        );
+
+        let msg = try_parse_msg("STATUSTEXT", &mut reader)?;
+        assert_eq!(msg.crc_extra, 83);
+        Ok(())
+    }
+
+    #[test]
+    fn test_try_parse_msg_crc_extra_of_targeted_msg() -> Result<(), MsgParseError> {
+        let mut reader = reader_from_str(
+            r#"<message id="76" name="COMMAND_LONG">
+                <field type="uint8_t" name="target_system">System which should execute the command</field>
+                <field type="uint8_t" name="target_component">Component which should execute the command, 0 for all components</field>
+                <field type="uint16_t" name="command" enum="MAV_CMD">Command ID (of command to send).</field>
+                <field type="uint8_t" name="confirmation">0: First transmission of this command.</field>
+                <field type="float" name="param1">Parameter 1 (for the specific command).</field>
+                <field type="float" name="param2">Parameter 2 (for the specific command).</field>
+                <field type="float" name="param3">Parameter 3 (for the specific command).</field>
+                <field type="float" name="param4">Parameter 4 (for the specific command).</field>
+                <field type="float" name="param5">Parameter 5 (for the specific command).</field>
+                <field type="float" name="param6">Parameter 6 (for the specific command).</field>
+                <field type="float" name="param7">Parameter 7 (for the specific command).</field>
+            </message>"#,
+        );
+
+        let msg = try_parse_msg("COMMAND_LONG", &mut reader)?;
+        assert_eq!(msg.crc_extra, 152);
+        assert_eq!(msg.offsets, Some(Offsets::new(30, Some(31))));
+        Ok(())
+    }
+
     #[test]
     fn test_try_get_offsets_from_msg() -> Result<(), MsgParseError> {
         let mut reader = reader_from_str(
@@ -451,8 +572,10 @@ mod tests {
     fn test_message_field_size() {
         let field = MessageField {
             name: "something".to_string(),
+            c_type: "uint16_t".to_string(),
             kind: MessageFieldKind::U16,
             multiplicity: NonZeroUsize::new(3).unwrap(),
+            is_array: true,
         };
 
         assert_eq!(field.size(), 6);
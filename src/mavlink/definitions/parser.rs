@@ -1,11 +1,11 @@
 use log::{debug, info};
 use quick_xml::{events::Event, reader::Reader};
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::path::{Path, PathBuf};
 use thiserror::Error;
 
-use super::msg_parser::try_get_offsets_from_msg;
-use super::TargetedMessage;
+use super::msg_parser::try_parse_msg;
+use super::{TargetedMessage, ID};
 
 #[derive(Debug, Error)]
 pub enum ParseError {
@@ -19,6 +19,8 @@ pub enum ParseError {
     NotAFile(PathBuf),
     #[error("A message definition does not have an ID.")]
     MessageWithoutId,
+    #[error("A message definition does not have a name.")]
+    MessageWithoutName,
     #[error("A message definition has an invalid ID.")]
     InvalidMessageId(#[from] std::num::ParseIntError),
     #[error("Found multiple targeted messages with the same ID.")]
@@ -29,6 +31,7 @@ pub enum ParseError {
 
 pub struct Parser {
     pub targeted_messages: Vec<TargetedMessage>,
+    pub crc_extras: HashMap<ID, u8>,
     visited_xml_files: HashSet<PathBuf>,
 }
 
@@ -36,6 +39,7 @@ impl Parser {
     pub fn new() -> Self {
         Self {
             targeted_messages: Vec::new(),
+            crc_extras: HashMap::new(),
             visited_xml_files: HashSet::new(),
         }
     }
@@ -89,9 +93,14 @@ impl Parser {
                                     .parse::<u32>()
                                     .map_err(ParseError::InvalidMessageId)
                             })?;
-
-                        let offsets = try_get_offsets_from_msg(&mut reader)?;
-                        if let Some(offsets) = offsets {
+                        let name = e
+                            .try_get_attribute("name")?
+                            .ok_or(ParseError::MessageWithoutName)?
+                            .unescape_value()?;
+
+                        let msg = try_parse_msg(&name, &mut reader)?;
+                        self.crc_extras.insert(id, msg.crc_extra);
+                        if let Some(offsets) = msg.offsets {
                             self.targeted_messages.push(TargetedMessage { id, offsets });
                         }
                     }
@@ -137,6 +146,22 @@ mod tests {
         for msg in &parser.targeted_messages {
             assert_eq!(msg.offsets, expected[&msg.id]);
         }
+        assert_eq!(parser.crc_extras.len(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_content_no_msg_name() -> Result<(), ParseError> {
+        let content = r#"
+            <mavlink>
+                <message id="1">
+                    <field type="uint8_t" name="target_system">Target system ID</field>
+                </message>
+            </mavlink>
+        "#;
+        let mut parser = Parser::new();
+        let result = parser.parse_content(content, Path::new(""));
+        assert!(matches!(result, Err(ParseError::MessageWithoutName)));
         Ok(())
     }
 
@@ -1,5 +1,5 @@
-use super::definitions::Offsets;
-use super::{v1, v2, Message, RoutingInfo, SysCompId};
+use super::definitions::{Definitions, Offsets};
+use super::{crc, v1, v2, Message, RoutingInfo, SysCompId};
 use anyhow::Result;
 use log::debug;
 use std::{collections::HashMap, sync::Arc};
@@ -18,11 +18,15 @@ pub enum DeserializationError {
 #[derive(Debug)]
 pub struct Deserializer {
     offsets: HashMap<u32, Offsets>,
+    crc_extras: HashMap<u32, u8>,
 }
 
 impl Deserializer {
-    pub fn new(offsets: HashMap<u32, Offsets>) -> Self {
-        Self { offsets }
+    pub fn new(definitions: Definitions) -> Self {
+        Self {
+            offsets: definitions.offsets,
+            crc_extras: definitions.crc_extras,
+        }
     }
 
     pub fn deserialize(&self, msg: Arc<[u8]>) -> Result<Message, DeserializationError> {
@@ -45,6 +49,7 @@ impl Deserializer {
             return Err(DeserializationError::InvalidLength(expected_len, msg.len()));
         }
 
+        let seq = msg[2];
         let sender = (msg[3], msg[4]).into();
         let msg_id = msg[5] as u32;
 
@@ -52,11 +57,16 @@ impl Deserializer {
 
         // The payload is the message minus the header and checksum.
         let payload = &msg[v1::HEADER_LEN..payload_len + v1::HEADER_LEN];
+        let checksum_start = v1::HEADER_LEN + payload_len;
+        let checksum = u16::from_le_bytes([msg[checksum_start], msg[checksum_start + 1]]);
 
         let target = self.target_from_payload(msg_id, payload);
 
         Ok(Message {
             routing_info: RoutingInfo { sender, target },
+            msg_id,
+            seq,
+            checksum,
             data: msg,
         })
     }
@@ -76,6 +86,7 @@ impl Deserializer {
             return Err(DeserializationError::InvalidLength(expected_len, msg.len()));
         }
 
+        let seq = msg[4];
         let sender = (msg[5], msg[6]).into();
         let msg_id = u32::from_le_bytes([msg[7], msg[8], msg[9], 0]);
 
@@ -83,15 +94,124 @@ impl Deserializer {
 
         // The payload is the message minus the header and checksum.
         let payload = &msg[v2::HEADER_LEN..payload_len + v2::HEADER_LEN];
+        let checksum_start = v2::HEADER_LEN + payload_len;
+        let checksum = u16::from_le_bytes([msg[checksum_start], msg[checksum_start + 1]]);
 
         let target = self.target_from_payload(msg_id, payload);
 
         Ok(Message {
             routing_info: RoutingInfo { sender, target },
+            msg_id,
+            seq,
+            checksum,
             data: msg,
         })
     }
 
+    /// Whether the checksum of a complete packet matches, packets of unknown messages can't be
+    /// checked and are accepted as they are.
+    pub fn has_valid_checksum(&self, packet: &[u8]) -> bool {
+        let (header_len, msg_id) = match packet.first() {
+            Some(&v1::PACKET_MAGIC) if packet.len() >= v1::HEADER_LEN => {
+                (v1::HEADER_LEN, packet[5] as u32)
+            }
+            Some(&v2::PACKET_MAGIC) if packet.len() >= v2::HEADER_LEN => (
+                v2::HEADER_LEN,
+                u32::from_le_bytes([packet[7], packet[8], packet[9], 0]),
+            ),
+            _ => return false,
+        };
+        let crc_extra = match self.crc_extras.get(&msg_id) {
+            Some(crc_extra) => *crc_extra,
+            None => return true,
+        };
+        let checksum_start = header_len + packet[1] as usize;
+        match packet.get(checksum_start..checksum_start + 2) {
+            Some(checksum) => {
+                u16::from_le_bytes([checksum[0], checksum[1]])
+                    == crc::checksum(&packet[..checksum_start], crc_extra)
+            }
+            None => false,
+        }
+    }
+
+    /// Returns a copy of the message with a different sender, or `None` if it can't be rewritten.
+    pub fn with_sender(&self, msg: &Message, sender: SysCompId) -> Option<Message> {
+        self.rewrite(msg, |data, _| {
+            let sys_id_idx = match data[0] {
+                v1::PACKET_MAGIC => 3,
+                _ => 5,
+            };
+            data[sys_id_idx] = sender.sys_id();
+            data[sys_id_idx + 1] = sender.comp_id();
+            Some(())
+        })
+        .map(|mut msg| {
+            msg.routing_info.sender = sender;
+            msg
+        })
+    }
+
+    /// Returns a copy of the message with a different target, or `None` if it can't be rewritten.
+    pub fn with_target(&self, msg: &Message, target: SysCompId) -> Option<Message> {
+        let offsets = self.offsets.get(&msg.msg_id)?;
+        self.rewrite(msg, |data, header_len| {
+            let payload_len = data[1] as usize;
+            let needed_len = offsets.component_id.unwrap_or(0).max(offsets.system_id) + 1;
+            if needed_len > payload_len {
+                // MAVLink 2 truncates trailing zeros of the payload, so they need to be restored.
+                if data[0] != v2::PACKET_MAGIC {
+                    return None;
+                }
+                let payload_end = header_len + payload_len;
+                data.splice(
+                    payload_end..payload_end,
+                    std::iter::repeat_n(0, needed_len - payload_len),
+                );
+                data[1] = needed_len as u8;
+            }
+
+            data[header_len + offsets.system_id] = target.sys_id();
+            if let Some(component_id) = offsets.component_id {
+                data[header_len + component_id] = target.comp_id();
+            }
+            Some(())
+        })
+        .map(|mut msg| {
+            msg.routing_info.target = target;
+            msg
+        })
+    }
+
+    // Applies the change to a copy of the packet and recomputes its checksum.
+    fn rewrite(
+        &self,
+        msg: &Message,
+        change: impl FnOnce(&mut Vec<u8>, usize) -> Option<()>,
+    ) -> Option<Message> {
+        let crc_extra = *self.crc_extras.get(&msg.msg_id)?;
+        let header_len = match msg.data.first() {
+            Some(&v1::PACKET_MAGIC) => v1::HEADER_LEN,
+            // Changing a signed message would invalidate its signature.
+            Some(&v2::PACKET_MAGIC) if msg.data[2] & v2::IFLAG_SIGNED != 0 => return None,
+            Some(&v2::PACKET_MAGIC) => v2::HEADER_LEN,
+            _ => return None,
+        };
+
+        let mut data = msg.data.to_vec();
+        change(&mut data, header_len)?;
+
+        let checksum_start = header_len + data[1] as usize;
+        let checksum = crc::checksum(&data[..checksum_start], crc_extra);
+        data[checksum_start..checksum_start + 2].copy_from_slice(&checksum.to_le_bytes());
+
+        Some(Message {
+            checksum,
+            data: data.into(),
+            ..msg.clone()
+        })
+    }
+
     fn target_from_payload(&self, msg_id: u32, payload: &[u8]) -> SysCompId {
         self.offsets
             .get(&msg_id)
@@ -108,3 +228,174 @@ impl Deserializer {
             .into()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COMMAND_LONG_ID: u32 = 76;
+
+    fn deserializer() -> Deserializer {
+        Deserializer::new(Definitions {
+            offsets: HashMap::from([(COMMAND_LONG_ID, Offsets::new(30, Some(31)))]),
+            crc_extras: HashMap::from([(0, 50), (COMMAND_LONG_ID, 152)]),
+        })
+    }
+
+    fn packet_v2(sender: (u8, u8), msg_id: u32, payload: &[u8], crc_extra: u8) -> Arc<[u8]> {
+        let [id0, id1, id2, _] = msg_id.to_le_bytes();
+        let mut data = vec![
+            v2::PACKET_MAGIC,
+            payload.len() as u8,
+            0,
+            0,
+            7,
+            sender.0,
+            sender.1,
+            id0,
+            id1,
+            id2,
+        ];
+        data.extend_from_slice(payload);
+        let checksum = crc::checksum(&data, crc_extra);
+        data.extend_from_slice(&checksum.to_le_bytes());
+        data.into()
+    }
+
+    fn packet_v1(sender: (u8, u8), msg_id: u8, payload: &[u8], crc_extra: u8) -> Arc<[u8]> {
+        let mut data = vec![
+            v1::PACKET_MAGIC,
+            payload.len() as u8,
+            7,
+            sender.0,
+            sender.1,
+            msg_id,
+        ];
+        data.extend_from_slice(payload);
+        let checksum = crc::checksum(&data, crc_extra);
+        data.extend_from_slice(&checksum.to_le_bytes());
+        data.into()
+    }
+
+    fn command_long_payload(target: (u8, u8)) -> Vec<u8> {
+        let mut payload = vec![0; 33];
+        payload[30] = target.0;
+        payload[31] = target.1;
+        payload
+    }
+
+    #[test]
+    fn test_deserialize_v2() -> Result<(), DeserializationError> {
+        let packet = packet_v2((1, 1), COMMAND_LONG_ID, &command_long_payload((2, 3)), 152);
+        let msg = deserializer().deserialize(packet.clone())?;
+
+        assert_eq!(msg.routing_info.sender, (1, 1).into());
+        assert_eq!(msg.routing_info.target, (2, 3).into());
+        assert_eq!(msg.msg_id, COMMAND_LONG_ID);
+        assert_eq!(msg.seq, 7);
+        assert_eq!(msg.checksum, crc::checksum(&packet[..43], 152));
+        Ok(())
+    }
+
+    #[test]
+    fn test_with_sender() -> Result<(), DeserializationError> {
+        let deserializer = deserializer();
+        for packet in [
+            packet_v1((1, 1), 0, &[0; 9], 50),
+            packet_v2((1, 1), 0, &[0; 9], 50),
+        ] {
+            let msg = deserializer.deserialize(packet)?;
+            let rewritten = deserializer.with_sender(&msg, (5, 6).into()).unwrap();
+            let expected = match msg.data[0] {
+                v1::PACKET_MAGIC => packet_v1((5, 6), 0, &[0; 9], 50),
+                _ => packet_v2((5, 6), 0, &[0; 9], 50),
+            };
+
+            assert_eq!(rewritten.data, expected);
+            assert_eq!(rewritten.routing_info.sender, (5, 6).into());
+            assert_eq!(
+                deserializer.deserialize(rewritten.data)?.checksum,
+                rewritten.checksum
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_has_valid_checksum() {
+        let deserializer = deserializer();
+        let packet = packet_v2((1, 1), COMMAND_LONG_ID, &command_long_payload((2, 3)), 152);
+        assert!(deserializer.has_valid_checksum(&packet));
+
+        let mut corrupted = packet.to_vec();
+        corrupted[12] ^= 0xFF;
+        assert!(!deserializer.has_valid_checksum(&corrupted));
+
+        // Without its CRC_EXTRA, the checksum of a message can't be checked
+        assert!(deserializer.has_valid_checksum(&packet_v1((1, 1), 200, &[1, 2], 0)));
+    }
+
+    #[test]
+    fn test_with_target() -> Result<(), DeserializationError> {
+        let deserializer = deserializer();
+        let packet = packet_v2(
+            (255, 190),
+            COMMAND_LONG_ID,
+            &command_long_payload((2, 1)),
+            152,
+        );
+        let msg = deserializer.deserialize(packet)?;
+
+        let rewritten = deserializer.with_target(&msg, (1, 1).into()).unwrap();
+        let expected = packet_v2(
+            (255, 190),
+            COMMAND_LONG_ID,
+            &command_long_payload((1, 1)),
+            152,
+        );
+        assert_eq!(rewritten.data, expected);
+        assert_eq!(rewritten.routing_info.target, (1, 1).into());
+        Ok(())
+    }
+
+    #[test]
+    fn test_with_target_restores_truncated_payload() -> Result<(), DeserializationError> {
+        let deserializer = deserializer();
+        let packet = packet_v2((255, 190), COMMAND_LONG_ID, &[0; 29], 152);
+        let msg = deserializer.deserialize(packet)?;
+        assert_eq!(msg.routing_info.target, (0, 0).into());
+
+        let rewritten = deserializer.with_target(&msg, (1, 1).into()).unwrap();
+        let mut payload = command_long_payload((1, 1));
+        payload.truncate(32);
+        let expected = packet_v2((255, 190), COMMAND_LONG_ID, &payload, 152);
+        assert_eq!(rewritten.data, expected);
+        assert_eq!(
+            deserializer
+                .deserialize(rewritten.data)?
+                .routing_info
+                .target,
+            (1, 1).into()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_rewrite_fails_without_crc_extra() -> Result<(), DeserializationError> {
+        let deserializer = deserializer();
+        let msg = deserializer.deserialize(packet_v2((1, 1), 30, &[0; 28], 39))?;
+        assert!(deserializer.with_sender(&msg, (5, 6).into()).is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_rewrite_fails_for_signed_messages() -> Result<(), DeserializationError> {
+        let deserializer = deserializer();
+        let mut packet = packet_v2((1, 1), 0, &[0; 9], 50).to_vec();
+        packet[2] = v2::IFLAG_SIGNED;
+        packet.extend_from_slice(&[0; v2::SIGNATURE_LEN]);
+        let msg = deserializer.deserialize(packet.into())?;
+        assert!(deserializer.with_sender(&msg, (5, 6).into()).is_none());
+        Ok(())
+    }
+}
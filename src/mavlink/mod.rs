@@ -2,9 +2,12 @@ use std::sync::Arc;
 
 pub use self::deserializer::DeserializationError;
 pub use self::deserializer::Deserializer;
+pub use self::serializer::serialize_v2;
 
+mod crc;
 pub mod definitions;
 mod deserializer;
+mod serializer;
 
 pub mod v1 {
     pub const PACKET_MAGIC: u8 = 0xFE;
@@ -123,5 +126,8 @@ impl RoutingInfo {
 #[derive(Debug, Clone)]
 pub struct Message {
     pub routing_info: RoutingInfo,
+    pub msg_id: u32,
+    pub seq: u8,
+    pub checksum: u16,
     pub data: Arc<[u8]>,
 }
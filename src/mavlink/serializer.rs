@@ -0,0 +1,84 @@
+use super::{crc, v2, Message, RoutingInfo};
+
+/// Builds a MAVLink 2 packet from the given payload.
+pub fn serialize_v2(
+    routing_info: RoutingInfo,
+    seq: u8,
+    msg_id: u32,
+    payload: &[u8],
+    crc_extra: u8,
+) -> Message {
+    // Trailing zeros of the payload are truncated, but at least one byte has to remain.
+    let payload_len = payload
+        .iter()
+        .rposition(|&b| b != 0)
+        .map_or(1, |i| i + 1)
+        .min(payload.len());
+    let [id0, id1, id2, _] = msg_id.to_le_bytes();
+
+    let mut data = Vec::with_capacity(v2::HEADER_LEN + payload_len + v2::CHECKSUM_LEN);
+    data.extend_from_slice(&[
+        v2::PACKET_MAGIC,
+        payload_len as u8,
+        0, // incompat_flags
+        0, // compat_flags
+        seq,
+        routing_info.sender.sys_id(),
+        routing_info.sender.comp_id(),
+        id0,
+        id1,
+        id2,
+    ]);
+    data.extend_from_slice(&payload[..payload_len]);
+    let checksum = crc::checksum(&data, crc_extra);
+    data.extend_from_slice(&checksum.to_le_bytes());
+
+    Message {
+        routing_info,
+        msg_id,
+        seq,
+        checksum,
+        data: data.into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mavlink::{definitions::Definitions, Deserializer};
+    use std::collections::HashMap;
+
+    fn routing_info() -> RoutingInfo {
+        RoutingInfo {
+            sender: (1, 191).into(),
+            target: (0, 0).into(),
+        }
+    }
+
+    #[test]
+    fn test_serialize_v2_round_trip() -> Result<(), crate::mavlink::DeserializationError> {
+        let msg = serialize_v2(routing_info(), 42, 0, &[0, 0, 0, 0, 18, 8, 0, 4, 3], 50);
+        let deserializer = Deserializer::new(Definitions {
+            offsets: HashMap::new(),
+            crc_extras: HashMap::from([(0, 50)]),
+        });
+        let parsed = deserializer.deserialize(msg.data.clone())?;
+
+        assert_eq!(msg.data.len(), v2::HEADER_LEN + 9 + v2::CHECKSUM_LEN);
+        assert_eq!(parsed.routing_info.sender, (1, 191).into());
+        assert_eq!(parsed.msg_id, 0);
+        assert_eq!(parsed.seq, 42);
+        assert_eq!(parsed.checksum, msg.checksum);
+        Ok(())
+    }
+
+    #[test]
+    fn test_serialize_v2_truncates_trailing_zeros() {
+        let msg = serialize_v2(routing_info(), 0, 0, &[1, 2, 0, 0], 50);
+        assert_eq!(msg.data[1], 2);
+        assert_eq!(msg.data.len(), v2::HEADER_LEN + 2 + v2::CHECKSUM_LEN);
+
+        let msg = serialize_v2(routing_info(), 0, 0, &[0, 0], 50);
+        assert_eq!(msg.data[1], 1);
+    }
+}
@@ -0,0 +1,507 @@
+use log::{debug, info};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+
+use crate::{
+    endpoint::{target_database::TargetDatabase, Name},
+    log_error::LogError,
+    mavlink::DeserializationError,
+};
+
+// Requests are only used to tell the metrics apart from other paths, so they can be small.
+const MAX_REQUEST_SIZE: usize = 8192;
+
+const DESERIALIZATION_ERRORS: [&str; 3] = ["too_short", "invalid_length", "invalid_magic"];
+
+// How long a client may take to send its request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Why an endpoint dropped a message instead of sending it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DropReason {
+    /// It waited too long for bandwidth.
+    Bandwidth,
+    /// A newer message of the same kind replaced it while it was held back by a rate limit.
+    RateLimit,
+    /// The transmitter failed to send it.
+    SendFailed,
+    /// The endpoint's queue was full when the router passed it on.
+    QueueFull,
+}
+
+impl DropReason {
+    const ALL: [Self; 4] = [
+        Self::Bandwidth,
+        Self::RateLimit,
+        Self::SendFailed,
+        Self::QueueFull,
+    ];
+
+    fn label(self) -> &'static str {
+        match self {
+            Self::Bandwidth => "bandwidth",
+            Self::RateLimit => "rate_limit",
+            Self::SendFailed => "send_failed",
+            Self::QueueFull => "queue_full",
+        }
+    }
+}
+
+type Value = fn(&EndpointMetrics) -> u64;
+
+// The metrics with a single value per endpoint: name, type, help and value.
+const ENDPOINT_METRICS: [(&str, &str, &str, Value); 6] = [
+    (
+        "packets_received_total",
+        "counter",
+        "Packets received by an endpoint.",
+        |e| e.packets_in.load(Ordering::Relaxed),
+    ),
+    (
+        "bytes_received_total",
+        "counter",
+        "Bytes received by an endpoint.",
+        |e| e.bytes_in.load(Ordering::Relaxed),
+    ),
+    (
+        "packets_sent_total",
+        "counter",
+        "Packets sent by an endpoint, once for each peer they went to.",
+        |e| e.packets_out.load(Ordering::Relaxed),
+    ),
+    (
+        "bytes_sent_total",
+        "counter",
+        "Bytes sent by an endpoint, once for each peer they went to.",
+        |e| e.bytes_out.load(Ordering::Relaxed),
+    ),
+    (
+        "queued_messages",
+        "gauge",
+        "Messages waiting to be sent by an endpoint, including those held back by limits.",
+        |e| e.queued.load(Ordering::Relaxed) + e.held.load(Ordering::Relaxed),
+    ),
+    (
+        "known_targets",
+        "gauge",
+        "Components whose address an endpoint learned.",
+        |e| e.targets.target_count() as u64,
+    ),
+];
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Settings {
+    /// Where Prometheus can scrape the metrics from, at `/metrics`.
+    pub address: SocketAddr,
+}
+
+/// Counters of the router itself.
+#[derive(Default)]
+pub struct RouterMetrics {
+    routed: AtomicU64,
+    duplicates: AtomicU64,
+    loops: AtomicU64,
+}
+
+impl RouterMetrics {
+    pub fn routed(&self) {
+        self.routed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn duplicate(&self) {
+        self.duplicates.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn looping(&self) {
+        self.loops.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Counters of a single endpoint.
+pub struct EndpointMetrics {
+    name: Name,
+    packets_in: AtomicU64,
+    bytes_in: AtomicU64,
+    packets_out: AtomicU64,
+    bytes_out: AtomicU64,
+    deserialization_errors: [AtomicU64; DESERIALIZATION_ERRORS.len()],
+    // Messages in the endpoint's queue, and those held back by rate or bandwidth limits.
+    queued: AtomicU64,
+    held: AtomicU64,
+    bandwidth_drops: AtomicU64,
+    rate_limit_drops: AtomicU64,
+    queue_full_drops: AtomicU64,
+    // Shared with the transmitter, which counts the messages it fails to send.
+    failed_sends: Arc<AtomicU64>,
+    // Counts per message ID, received and sent
+    messages: Mutex<BTreeMap<u32, (u64, u64)>>,
+    targets: Arc<TargetDatabase>,
+}
+
+impl EndpointMetrics {
+    /// Creates the metrics of an endpoint, whose transmitter counts its failures in `failed_sends`.
+    pub fn new(name: Name, targets: Arc<TargetDatabase>, failed_sends: Arc<AtomicU64>) -> Self {
+        Self {
+            name,
+            packets_in: AtomicU64::new(0),
+            bytes_in: AtomicU64::new(0),
+            packets_out: AtomicU64::new(0),
+            bytes_out: AtomicU64::new(0),
+            deserialization_errors: Default::default(),
+            queued: AtomicU64::new(0),
+            held: AtomicU64::new(0),
+            bandwidth_drops: AtomicU64::new(0),
+            rate_limit_drops: AtomicU64::new(0),
+            queue_full_drops: AtomicU64::new(0),
+            failed_sends,
+            messages: Mutex::new(BTreeMap::new()),
+            targets,
+        }
+    }
+
+    pub fn packet_received(&self, bytes: usize) {
+        self.packets_in.fetch_add(1, Ordering::Relaxed);
+        self.bytes_in.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub fn packet_sent(&self, bytes: usize) {
+        self.packets_out.fetch_add(1, Ordering::Relaxed);
+        self.bytes_out.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub fn message_received(&self, msg_id: u32) {
+        self.messages.lock().entry(msg_id).or_default().0 += 1;
+    }
+
+    pub fn message_sent(&self, msg_id: u32) {
+        self.messages.lock().entry(msg_id).or_default().1 += 1;
+    }
+
+    pub fn deserialization_error(&self, error: &DeserializationError) {
+        let index = match error {
+            DeserializationError::TooShort => 0,
+            DeserializationError::InvalidLength(..) => 1,
+            DeserializationError::InvalidMagic(_) => 2,
+        };
+        self.deserialization_errors[index].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn enqueued(&self) {
+        self.queued.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn dequeued(&self) {
+        self.queued.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    /// Sets the number of messages held back by rate or bandwidth limits.
+    pub fn set_held(&self, held: usize) {
+        self.held.store(held as u64, Ordering::Relaxed);
+    }
+
+    pub fn dropped(&self, reason: DropReason, count: usize) {
+        self.drops(reason)
+            .fetch_add(count as u64, Ordering::Relaxed);
+    }
+
+    fn drops(&self, reason: DropReason) -> &AtomicU64 {
+        match reason {
+            DropReason::Bandwidth => &self.bandwidth_drops,
+            DropReason::RateLimit => &self.rate_limit_drops,
+            DropReason::SendFailed => &self.failed_sends,
+            DropReason::QueueFull => &self.queue_full_drops,
+        }
+    }
+}
+
+/// All metrics of a running router, including the endpoints which are currently running.
+pub struct Metrics {
+    router: Arc<RouterMetrics>,
+    endpoints: Mutex<Vec<Arc<EndpointMetrics>>>,
+}
+
+impl Metrics {
+    pub fn new(router: Arc<RouterMetrics>) -> Self {
+        Self {
+            router,
+            endpoints: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn add_endpoint(&self, endpoint: Arc<EndpointMetrics>) {
+        self.endpoints.lock().push(endpoint);
+    }
+
+    pub fn remove_endpoint(&self, name: &str) {
+        self.endpoints.lock().retain(|e| &*e.name != name);
+    }
+
+    /// Renders the metrics in the Prometheus text format.
+    pub fn render(&self) -> String {
+        let mut out = Output::default();
+        let router = &self.router;
+        let name = "routed_messages_total";
+        out.family(name, "counter", "Messages passed to the router.");
+        out.sample(name, &[], router.routed.load(Ordering::Relaxed));
+        let name = "router_dropped_messages_total";
+        out.family(name, "counter", "Messages dropped by the router.");
+        for (reason, counter) in [("duplicate", &router.duplicates), ("loop", &router.loops)] {
+            out.sample(name, &[("reason", reason)], counter.load(Ordering::Relaxed));
+        }
+
+        let endpoints = self.endpoints.lock().clone();
+        for (name, kind, help, value) in ENDPOINT_METRICS {
+            out.family(name, kind, help);
+            for endpoint in &endpoints {
+                out.sample(name, &[("endpoint", &endpoint.name)], value(endpoint));
+            }
+        }
+
+        let name = "dropped_messages_total";
+        out.family(name, "counter", "Messages dropped by an endpoint.");
+        for endpoint in &endpoints {
+            for reason in DropReason::ALL {
+                let labels = [("endpoint", &*endpoint.name), ("reason", reason.label())];
+                out.sample(
+                    name,
+                    &labels,
+                    endpoint.drops(reason).load(Ordering::Relaxed),
+                );
+            }
+        }
+
+        let name = "deserialization_errors_total";
+        out.family(name, "counter", "Packets which couldn't be deserialized.");
+        for endpoint in &endpoints {
+            for (kind, counter) in DESERIALIZATION_ERRORS
+                .iter()
+                .zip(&endpoint.deserialization_errors)
+            {
+                let labels = [("endpoint", &*endpoint.name), ("kind", kind)];
+                out.sample(name, &labels, counter.load(Ordering::Relaxed));
+            }
+        }
+
+        let name = "messages_total";
+        out.family(
+            name,
+            "counter",
+            "Messages received or sent by an endpoint, per ID.",
+        );
+        for endpoint in &endpoints {
+            for (msg_id, (received, sent)) in endpoint.messages.lock().iter() {
+                let msg_id = msg_id.to_string();
+                for (direction, value) in [("received", received), ("sent", sent)] {
+                    let labels = [
+                        ("endpoint", &*endpoint.name),
+                        ("msg_id", &msg_id),
+                        ("direction", direction),
+                    ];
+                    out.sample(name, &labels, *value);
+                }
+            }
+        }
+        out.text
+    }
+}
+
+#[derive(Default)]
+struct Output {
+    text: String,
+}
+
+impl Output {
+    fn family(&mut self, name: &str, kind: &str, help: &str) {
+        let _ = writeln!(self.text, "# HELP mavlink_shouter_{} {}", name, help);
+        let _ = writeln!(self.text, "# TYPE mavlink_shouter_{} {}", name, kind);
+    }
+
+    fn sample(&mut self, name: &str, labels: &[(&str, &str)], value: u64) {
+        let _ = write!(self.text, "mavlink_shouter_{}", name);
+        if !labels.is_empty() {
+            let labels: Vec<_> = labels
+                .iter()
+                .map(|(label, value)| format!("{}=\"{}\"", label, escape(value)))
+                .collect();
+            let _ = write!(self.text, "{{{}}}", labels.join(","));
+        }
+        let _ = writeln!(self.text, " {}", value);
+    }
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Serves the metrics over HTTP.
+pub struct Server {
+    listener: TcpListener,
+}
+
+impl Server {
+    /// Binds the listener right away, so an unavailable address is reported at startup.
+    pub fn bind(settings: &Settings) -> std::io::Result<Self> {
+        let listener = std::net::TcpListener::bind(settings.address)?;
+        listener.set_nonblocking(true)?;
+        info!(
+            "Serving metrics on http://{}/metrics",
+            listener.local_addr()?
+        );
+        Ok(Self {
+            listener: TcpListener::from_std(listener)?,
+        })
+    }
+
+    pub fn start(self, metrics: Arc<Metrics>) {
+        let listener = self.listener;
+        tokio::spawn(async move {
+            loop {
+                let (stream, addr) = match listener.accept().await {
+                    Ok(s) => s,
+                    Err(e) => {
+                        debug!("Error accepting metrics connection: {}", e);
+                        continue;
+                    }
+                };
+                debug!("Metrics requested by {}", addr);
+                let metrics = metrics.clone();
+                tokio::spawn(async move {
+                    match tokio::time::timeout(REQUEST_TIMEOUT, respond(stream, &metrics)).await {
+                        Ok(res) => {
+                            res.log_error();
+                        }
+                        Err(_) => debug!("Metrics request from {} timed out", addr),
+                    }
+                });
+            }
+        });
+    }
+}
+
+async fn respond(mut stream: TcpStream, metrics: &Metrics) -> std::io::Result<()> {
+    let mut request = Vec::new();
+    let mut buf = [0; 1024];
+    while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+        let n = stream.read(&mut buf).await?;
+        if n == 0 || request.len() + n > MAX_REQUEST_SIZE {
+            return Ok(());
+        }
+        request.extend_from_slice(&buf[..n]);
+    }
+
+    let request_line = request.split(|&b| b == b'\r').next().unwrap_or_default();
+    let mut parts = request_line.split(|&b| b == b' ');
+    let (status, body) = match (parts.next(), parts.next()) {
+        (Some(b"GET"), Some(b"/metrics")) => ("200 OK", metrics.render()),
+        _ => ("404 Not Found", String::new()),
+    };
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn endpoint(name: &str) -> Arc<EndpointMetrics> {
+        let targets = TargetDatabase::new(name.into(), Default::default(), &[]);
+        Arc::new(EndpointMetrics::new(
+            name.into(),
+            Arc::new(targets),
+            Default::default(),
+        ))
+    }
+
+    #[test]
+    fn test_render_endpoint_metrics() {
+        let metrics = Metrics::new(Default::default());
+        let gcs = endpoint("gcs");
+        metrics.add_endpoint(gcs.clone());
+        gcs.packet_received(20);
+        gcs.message_received(0);
+        gcs.message_sent(30);
+        gcs.deserialization_error(&DeserializationError::InvalidMagic(0));
+        gcs.enqueued();
+        gcs.enqueued();
+        gcs.dequeued();
+        gcs.set_held(2);
+        gcs.dropped(DropReason::QueueFull, 1);
+        gcs.failed_sends.fetch_add(1, Ordering::Relaxed);
+        gcs.dropped(DropReason::SendFailed, 1);
+
+        let text = metrics.render();
+        assert!(text.contains("# TYPE mavlink_shouter_packets_received_total counter\n"));
+        assert!(text.contains("mavlink_shouter_bytes_received_total{endpoint=\"gcs\"} 20\n"));
+        assert!(text.contains(
+            "mavlink_shouter_deserialization_errors_total{endpoint=\"gcs\",kind=\"invalid_magic\"} 1\n"
+        ));
+        assert!(text.contains(
+            "mavlink_shouter_messages_total{endpoint=\"gcs\",msg_id=\"30\",direction=\"sent\"} 1\n"
+        ));
+        assert!(text.contains("mavlink_shouter_queued_messages{endpoint=\"gcs\"} 3\n"));
+        assert!(text.contains(
+            "mavlink_shouter_dropped_messages_total{endpoint=\"gcs\",reason=\"queue_full\"} 1\n"
+        ));
+        assert!(text.contains(
+            "mavlink_shouter_dropped_messages_total{endpoint=\"gcs\",reason=\"send_failed\"} 2\n"
+        ));
+
+        metrics.remove_endpoint("gcs");
+        assert!(!metrics.render().contains("gcs"));
+    }
+
+    #[test]
+    fn test_escape_label_values() {
+        assert_eq!(escape("a\"b\\c\nd"), "a\\\"b\\\\c\\nd");
+    }
+
+    #[tokio::test]
+    async fn test_serve_metrics() -> std::io::Result<()> {
+        let metrics = Arc::new(Metrics::new(Default::default()));
+        let server = Server::bind(&Settings {
+            address: ([127, 0, 0, 1], 0).into(),
+        })?;
+        let address = server.listener.local_addr()?;
+        server.start(metrics);
+
+        let request = |path: &'static str| async move {
+            let mut stream = TcpStream::connect(address).await?;
+            let request = format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path);
+            stream.write_all(request.as_bytes()).await?;
+            let mut response = String::new();
+            stream.read_to_string(&mut response).await?;
+            Ok::<_, std::io::Error>(response)
+        };
+
+        let response = request("/metrics").await?;
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("mavlink_shouter_routed_messages_total 0\n"));
+        assert!(request("/")
+            .await?
+            .starts_with("HTTP/1.1 404 Not Found\r\n"));
+        Ok(())
+    }
+}
@@ -0,0 +1,69 @@
+use log::{error, info};
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use crate::{config, Handle};
+
+#[cfg(unix)]
+type Hangup = tokio::signal::unix::Signal;
+#[cfg(not(unix))]
+type Hangup = ();
+
+#[cfg(unix)]
+fn hangup() -> std::io::Result<Hangup> {
+    use tokio::signal::unix::{signal, SignalKind};
+    signal(SignalKind::hangup())
+}
+
+#[cfg(not(unix))]
+fn hangup() -> std::io::Result<Hangup> {
+    Ok(())
+}
+
+#[cfg(unix)]
+async fn hung_up(hangup: &mut Hangup) {
+    hangup.recv().await;
+}
+
+// There is no SIGHUP outside of Unix
+#[cfg(not(unix))]
+async fn hung_up(_: &mut Hangup) {
+    std::future::pending().await
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// Reloads the configuration file on SIGHUP, and whenever it changes if a poll interval is set.
+/// The overrides are applied to every reloaded configuration.
+pub async fn watch(
+    path: PathBuf,
+    overrides: config::Overrides,
+    handle: Handle,
+    poll_interval: Option<Duration>,
+) -> std::io::Result<()> {
+    let mut hangup = hangup()?;
+    let mut poll = tokio::time::interval(poll_interval.unwrap_or(Duration::from_secs(1)));
+    let mut last_modified = modified(&path);
+    loop {
+        tokio::select! {
+            _ = hung_up(&mut hangup) => info!("Received SIGHUP"),
+            _ = poll.tick(), if poll_interval.is_some() => {
+                let modified = modified(&path);
+                if modified == last_modified {
+                    continue;
+                }
+                last_modified = modified;
+                info!("Configuration file {} changed", path.display());
+            }
+        }
+
+        match overrides.load(Some(&path)) {
+            Ok(settings) => handle.reload(settings).await,
+            Err(e) => error!("Failed to reload configuration: {}", e),
+        }
+    }
+}
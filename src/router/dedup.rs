@@ -0,0 +1,143 @@
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, time::Duration};
+use tokio::time::Instant;
+
+use crate::mavlink;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Settings {
+    /// The names of endpoints which are redundant paths to the same vehicle.
+    pub endpoints: Vec<String>,
+    /// How long a message is remembered to detect duplicates of it.
+    #[serde(default = "default_window_ms")]
+    pub window_ms: u64,
+}
+
+fn default_window_ms() -> u64 {
+    500
+}
+
+type Key = (mavlink::SysCompId, u8, u32, u16);
+
+fn key(msg: &mavlink::Message) -> Key {
+    (msg.routing_info.sender, msg.seq, msg.msg_id, msg.checksum)
+}
+
+struct Group {
+    endpoints: Vec<String>,
+    window: Duration,
+    seen: HashMap<Key, Instant>,
+    last_pruned: Instant,
+}
+
+impl Group {
+    fn is_duplicate(&mut self, msg: &mavlink::Message, now: Instant) -> bool {
+        if now.duration_since(self.last_pruned) >= self.window {
+            let window = self.window;
+            self.seen
+                .retain(|_, seen| now.duration_since(*seen) < window);
+            self.last_pruned = now;
+        }
+
+        match self.seen.insert(key(msg), now) {
+            Some(seen) => now.duration_since(seen) < self.window,
+            None => false,
+        }
+    }
+}
+
+/// Drops copies of messages which arrive over more than one endpoint of a redundant group.
+pub struct Deduplicator {
+    groups: Vec<Group>,
+}
+
+impl Deduplicator {
+    pub fn new(settings: &[Settings]) -> Self {
+        let now = Instant::now();
+        let groups = settings
+            .iter()
+            .map(|s| Group {
+                endpoints: s.endpoints.clone(),
+                window: Duration::from_millis(s.window_ms),
+                seen: HashMap::new(),
+                last_pruned: now,
+            })
+            .collect();
+        Self { groups }
+    }
+
+    pub fn is_duplicate(&mut self, source: &str, msg: &mavlink::Message, now: Instant) -> bool {
+        // Every group of the source has to remember the message, so don't short-circuit.
+        let mut duplicate = false;
+        for group in self
+            .groups
+            .iter_mut()
+            .filter(|group| group.endpoints.iter().any(|e| e == source))
+        {
+            duplicate |= group.is_duplicate(msg, now);
+        }
+        duplicate
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(seq: u8, checksum: u16) -> mavlink::Message {
+        mavlink::Message {
+            routing_info: mavlink::RoutingInfo {
+                sender: (1, 1).into(),
+                target: (0, 0).into(),
+            },
+            msg_id: 30,
+            seq,
+            checksum,
+            data: [0].into(),
+        }
+    }
+
+    fn deduplicator() -> Deduplicator {
+        Deduplicator::new(&[Settings {
+            endpoints: vec!["radio".to_string(), "lte".to_string()],
+            window_ms: 500,
+        }])
+    }
+
+    #[test]
+    fn test_is_duplicate_within_window() {
+        let mut dedup = deduplicator();
+        let now = Instant::now();
+
+        assert!(!dedup.is_duplicate("radio", &message(1, 42), now));
+        assert!(dedup.is_duplicate("lte", &message(1, 42), now + Duration::from_millis(100)));
+    }
+
+    #[test]
+    fn test_is_duplicate_after_window() {
+        let mut dedup = deduplicator();
+        let now = Instant::now();
+
+        assert!(!dedup.is_duplicate("radio", &message(1, 42), now));
+        assert!(!dedup.is_duplicate("lte", &message(1, 42), now + Duration::from_millis(500)));
+    }
+
+    #[test]
+    fn test_is_duplicate_distinguishes_messages() {
+        let mut dedup = deduplicator();
+        let now = Instant::now();
+
+        assert!(!dedup.is_duplicate("radio", &message(1, 42), now));
+        assert!(!dedup.is_duplicate("lte", &message(2, 42), now));
+        assert!(!dedup.is_duplicate("lte", &message(1, 43), now));
+    }
+
+    #[test]
+    fn test_is_duplicate_ignores_endpoints_outside_groups() {
+        let mut dedup = deduplicator();
+        let now = Instant::now();
+
+        assert!(!dedup.is_duplicate("gcs", &message(1, 42), now));
+        assert!(!dedup.is_duplicate("gcs", &message(1, 42), now));
+    }
+}
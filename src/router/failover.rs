@@ -0,0 +1,396 @@
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::{sync::Arc, time::Duration};
+use tokio::time::Instant;
+
+use super::HEARTBEAT_ID;
+use crate::{endpoint::LinkStats, mavlink};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Settings {
+    pub name: String,
+    /// The system ID of the vehicle, only traffic targeted at it is subject to failover.
+    pub sys_id: u8,
+    /// The endpoint which is used whenever it is healthy.
+    pub primary: String,
+    /// The endpoints to fall back to, in order of preference.
+    pub backups: Vec<String>,
+    /// How long a link may go without a heartbeat before it is considered unhealthy.
+    #[serde(default = "default_heartbeat_timeout_ms")]
+    pub heartbeat_timeout_ms: u64,
+    /// The share of lost messages above which a link is considered unhealthy.
+    #[serde(default = "default_max_loss")]
+    pub max_loss: f64,
+    /// How long the primary has to be healthy again before switching back to it.
+    #[serde(default = "default_hold_ms")]
+    pub hold_ms: u64,
+}
+
+fn default_heartbeat_timeout_ms() -> u64 {
+    3000
+}
+
+fn default_max_loss() -> f64 {
+    0.2
+}
+
+fn default_hold_ms() -> u64 {
+    5000
+}
+
+struct Link {
+    endpoint: String,
+    stats: Option<Arc<LinkStats>>,
+    last_heartbeat: Option<Instant>,
+    // The received and lost totals at the last evaluation.
+    totals: (u64, u64),
+    loss: f64,
+    healthy_since: Option<Instant>,
+}
+
+impl Link {
+    fn new(endpoint: String) -> Self {
+        Self {
+            endpoint,
+            stats: None,
+            last_heartbeat: None,
+            totals: (0, 0),
+            loss: 0.0,
+            healthy_since: None,
+        }
+    }
+
+    // Only the components of the vehicle count, other senders may share the link.
+    fn update_loss(&mut self, sys_id: u8) {
+        let stats = match &self.stats {
+            Some(stats) => stats.snapshot(),
+            None => return,
+        };
+        let totals = stats
+            .iter()
+            .filter(|(sender, _)| sender.sys_id() == sys_id)
+            .fold((0, 0), |(received, lost), (_, s)| {
+                (received + s.received, lost + s.lost)
+            });
+
+        let received = totals.0.saturating_sub(self.totals.0);
+        let lost = totals.1.saturating_sub(self.totals.1);
+        self.totals = totals;
+        if received + lost > 0 {
+            self.loss = lost as f64 / (received + lost) as f64;
+        }
+    }
+
+    fn is_healthy(&self, now: Instant, heartbeat_timeout: Duration, max_loss: f64) -> bool {
+        let has_heartbeat = self
+            .last_heartbeat
+            .is_some_and(|t| now.duration_since(t) <= heartbeat_timeout);
+        has_heartbeat && self.loss <= max_loss
+    }
+}
+
+struct Group {
+    name: String,
+    sys_id: u8,
+    // The primary link comes first, followed by the backups.
+    links: Vec<Link>,
+    active: usize,
+    heartbeat_timeout: Duration,
+    max_loss: f64,
+    hold: Duration,
+}
+
+impl Group {
+    fn evaluate(&mut self, now: Instant) {
+        for link in &mut self.links {
+            link.update_loss(self.sys_id);
+            if !link.is_healthy(now, self.heartbeat_timeout, self.max_loss) {
+                link.healthy_since = None;
+            } else if link.healthy_since.is_none() {
+                link.healthy_since = Some(now);
+            }
+        }
+
+        let primary_is_stable = self.links[0]
+            .healthy_since
+            .is_some_and(|t| now.duration_since(t) >= self.hold);
+        let next = if primary_is_stable {
+            Some(0)
+        } else if self.links[self.active].healthy_since.is_none() {
+            self.links.iter().position(|l| l.healthy_since.is_some())
+        } else {
+            None
+        };
+
+        match next {
+            Some(next) if next != self.active => {
+                warn!(
+                    "[{}] Switching from link '{}' to '{}'",
+                    self.name, self.links[self.active].endpoint, self.links[next].endpoint
+                );
+                self.active = next;
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Sends targeted traffic of link groups only over their healthiest link.
+pub struct Failover {
+    groups: Vec<Group>,
+}
+
+impl Failover {
+    pub fn new(settings: &[Settings]) -> Self {
+        let groups = settings
+            .iter()
+            .map(|s| Group {
+                name: s.name.clone(),
+                sys_id: s.sys_id,
+                links: std::iter::once(&s.primary)
+                    .chain(&s.backups)
+                    .cloned()
+                    .map(Link::new)
+                    .collect(),
+                active: 0,
+                heartbeat_timeout: Duration::from_millis(s.heartbeat_timeout_ms),
+                max_loss: s.max_loss,
+                hold: Duration::from_millis(s.hold_ms),
+            })
+            .inspect(|g| info!("[{}] Using link '{}'", g.name, g.links[0].endpoint))
+            .collect();
+        Self { groups }
+    }
+
+    fn links_mut<'a>(&'a mut self, endpoint: &'a str) -> impl Iterator<Item = &'a mut Link> {
+        self.groups
+            .iter_mut()
+            .flat_map(|g| g.links.iter_mut())
+            .filter(move |l| l.endpoint == endpoint)
+    }
+
+    pub fn add_link_stats(&mut self, endpoint: &str, stats: Arc<LinkStats>) {
+        for link in self.links_mut(endpoint) {
+            link.stats = Some(stats.clone());
+        }
+    }
+
+    pub fn record(&mut self, source: &str, msg: &mavlink::Message, now: Instant) {
+        if msg.msg_id != HEARTBEAT_ID {
+            return;
+        }
+        // Only the vehicle's own heartbeats prove that a link reaches it
+        let links = self
+            .groups
+            .iter_mut()
+            .filter(|g| g.sys_id == msg.routing_info.sender.sys_id())
+            .flat_map(|g| g.links.iter_mut())
+            .filter(|l| l.endpoint == source);
+        for link in links {
+            link.last_heartbeat = Some(now);
+        }
+    }
+
+    pub fn evaluate(&mut self, now: Instant) {
+        for group in &mut self.groups {
+            group.evaluate(now);
+        }
+    }
+
+    /// Whether the endpoint is a link which must not be used for the message right now.
+    pub fn is_standby(&self, endpoint: &str, msg: &mavlink::Message) -> bool {
+        if msg.routing_info.target.is_broadcast() {
+            return false;
+        }
+        let target = msg.routing_info.target.sys_id();
+        self.groups.iter().filter(|g| g.sys_id == target).any(|g| {
+            g.links
+                .iter()
+                .enumerate()
+                .any(|(i, l)| l.endpoint == endpoint && i != g.active)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(msg_id: u32, target: (u8, u8)) -> mavlink::Message {
+        mavlink::Message {
+            routing_info: mavlink::RoutingInfo {
+                sender: (1, 1).into(),
+                target: target.into(),
+            },
+            msg_id,
+            seq: 0,
+            checksum: 0,
+            data: [0].into(),
+        }
+    }
+
+    fn failover() -> Failover {
+        Failover::new(&[Settings {
+            name: "vehicle".to_string(),
+            sys_id: 1,
+            primary: "radio".to_string(),
+            backups: vec!["lte".to_string()],
+            heartbeat_timeout_ms: 3000,
+            max_loss: 0.2,
+            hold_ms: 5000,
+        }])
+    }
+
+    fn heartbeat(failover: &mut Failover, endpoint: &str, now: Instant) {
+        failover.record(endpoint, &message(HEARTBEAT_ID, (0, 0)), now);
+    }
+
+    #[test]
+    fn test_primary_is_active_initially() {
+        let failover = failover();
+        let targeted = message(76, (1, 1));
+
+        assert!(!failover.is_standby("radio", &targeted));
+        assert!(failover.is_standby("lte", &targeted));
+        assert!(!failover.is_standby("gcs", &targeted));
+    }
+
+    #[test]
+    fn test_broadcasts_use_all_links() {
+        let failover = failover();
+        assert!(!failover.is_standby("lte", &message(76, (0, 0))));
+    }
+
+    #[test]
+    fn test_other_systems_use_all_links() {
+        let failover = failover();
+        // Another vehicle or a GCS which is reached over the backup link as well
+        assert!(!failover.is_standby("lte", &message(76, (2, 1))));
+        assert!(!failover.is_standby("lte", &message(76, (255, 190))));
+    }
+
+    #[test]
+    fn test_ignores_heartbeats_of_other_systems() {
+        let mut failover = failover();
+        let targeted = message(76, (1, 1));
+        let now = Instant::now();
+
+        let other = mavlink::Message {
+            routing_info: mavlink::RoutingInfo {
+                sender: (2, 1).into(),
+                target: (0, 0).into(),
+            },
+            ..message(HEARTBEAT_ID, (0, 0))
+        };
+        failover.record("lte", &other, now);
+        failover.evaluate(now);
+        assert!(failover.is_standby("lte", &targeted));
+    }
+
+    #[test]
+    fn test_switches_to_backup_when_primary_is_silent() {
+        let mut failover = failover();
+        let targeted = message(76, (1, 1));
+        let now = Instant::now();
+
+        heartbeat(&mut failover, "radio", now);
+        heartbeat(&mut failover, "lte", now);
+        failover.evaluate(now);
+        assert!(failover.is_standby("lte", &targeted));
+
+        let later = now + Duration::from_millis(3500);
+        heartbeat(&mut failover, "lte", later);
+        failover.evaluate(later);
+        assert!(failover.is_standby("radio", &targeted));
+        assert!(!failover.is_standby("lte", &targeted));
+    }
+
+    #[test]
+    fn test_switches_back_after_hold_time() {
+        let mut failover = failover();
+        let targeted = message(76, (1, 1));
+        let now = Instant::now();
+
+        heartbeat(&mut failover, "lte", now);
+        failover.evaluate(now);
+        assert!(!failover.is_standby("lte", &targeted));
+
+        // The primary recovers, but has to stay healthy for the hold time.
+        let recovered = now + Duration::from_millis(1000);
+        heartbeat(&mut failover, "radio", recovered);
+        heartbeat(&mut failover, "lte", recovered);
+        failover.evaluate(recovered);
+        assert!(!failover.is_standby("lte", &targeted));
+
+        let stable = recovered + Duration::from_millis(5000);
+        heartbeat(&mut failover, "radio", stable);
+        heartbeat(&mut failover, "lte", stable);
+        failover.evaluate(stable);
+        assert!(!failover.is_standby("radio", &targeted));
+        assert!(failover.is_standby("lte", &targeted));
+    }
+
+    #[test]
+    fn test_keeps_active_link_when_nothing_is_healthy() {
+        let mut failover = failover();
+        let targeted = message(76, (1, 1));
+
+        failover.evaluate(Instant::now());
+        assert!(!failover.is_standby("radio", &targeted));
+    }
+
+    #[test]
+    fn test_switches_on_high_loss() {
+        let mut failover = failover();
+        let targeted = message(76, (1, 1));
+        let now = Instant::now();
+
+        let stats = Arc::new(LinkStats::new("radio".into()));
+        failover.add_link_stats("radio", stats.clone());
+        for seq in [0, 5, 10] {
+            stats.record(&mavlink::Message {
+                seq,
+                ..message(30, (0, 0))
+            });
+        }
+
+        heartbeat(&mut failover, "radio", now);
+        heartbeat(&mut failover, "lte", now);
+        failover.evaluate(now);
+        assert!(failover.is_standby("radio", &targeted));
+        assert!(!failover.is_standby("lte", &targeted));
+    }
+
+    #[test]
+    fn test_ignores_loss_of_other_systems() {
+        let mut failover = failover();
+        let targeted = message(76, (1, 1));
+        let now = Instant::now();
+
+        let stats = Arc::new(LinkStats::new("radio".into()));
+        failover.add_link_stats("radio", stats.clone());
+        // A GCS on the same link loses messages, the vehicle doesn't
+        for seq in [0, 5, 10] {
+            stats.record(&mavlink::Message {
+                routing_info: mavlink::RoutingInfo {
+                    sender: (255, 190).into(),
+                    target: (0, 0).into(),
+                },
+                seq,
+                ..message(30, (0, 0))
+            });
+        }
+        for seq in [0, 1, 2] {
+            stats.record(&mavlink::Message {
+                seq,
+                ..message(30, (0, 0))
+            });
+        }
+
+        heartbeat(&mut failover, "radio", now);
+        heartbeat(&mut failover, "lte", now);
+        failover.evaluate(now);
+        assert!(!failover.is_standby("radio", &targeted));
+        assert!(failover.is_standby("lte", &targeted));
+    }
+}
@@ -0,0 +1,107 @@
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+use super::HEARTBEAT_ID;
+use crate::mavlink;
+
+const HEARTBEAT_CRC_EXTRA: u8 = 50;
+const MAV_TYPE_ONBOARD_CONTROLLER: u8 = 18;
+const MAV_AUTOPILOT_INVALID: u8 = 8;
+const MAV_STATE_ACTIVE: u8 = 4;
+const MAVLINK_VERSION: u8 = 3;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Settings {
+    pub sys_id: u8,
+    /// Defaults to MAV_COMP_ID_ONBOARD_COMPUTER.
+    #[serde(default = "default_comp_id")]
+    pub comp_id: u8,
+    #[serde(default = "default_heartbeat_interval_ms")]
+    pub heartbeat_interval_ms: u64,
+}
+
+fn default_comp_id() -> u8 {
+    191
+}
+
+fn default_heartbeat_interval_ms() -> u64 {
+    1000
+}
+
+/// The router's own component, which announces itself with heartbeats.
+pub struct Identity {
+    id: mavlink::SysCompId,
+    seq: u8,
+    interval: Duration,
+}
+
+impl Identity {
+    pub fn new(settings: &Settings) -> Self {
+        Self {
+            id: (settings.sys_id, settings.comp_id).into(),
+            seq: 0,
+            // A zero interval would make the heartbeat timer panic.
+            interval: Duration::from_millis(settings.heartbeat_interval_ms.max(1)),
+        }
+    }
+
+    pub fn id(&self) -> mavlink::SysCompId {
+        self.id
+    }
+
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    pub fn heartbeat(&mut self) -> mavlink::Message {
+        let custom_mode = 0u32.to_le_bytes();
+        let payload = [
+            custom_mode[0],
+            custom_mode[1],
+            custom_mode[2],
+            custom_mode[3],
+            MAV_TYPE_ONBOARD_CONTROLLER,
+            MAV_AUTOPILOT_INVALID,
+            0, // base_mode
+            MAV_STATE_ACTIVE,
+            MAVLINK_VERSION,
+        ];
+        let routing_info = mavlink::RoutingInfo {
+            sender: self.id,
+            target: (0, 0).into(),
+        };
+
+        let msg = mavlink::serialize_v2(
+            routing_info,
+            self.seq,
+            HEARTBEAT_ID,
+            &payload,
+            HEARTBEAT_CRC_EXTRA,
+        );
+        self.seq = self.seq.wrapping_add(1);
+        msg
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_heartbeat() {
+        let mut identity = Identity::new(&Settings {
+            sys_id: 1,
+            comp_id: 191,
+            heartbeat_interval_ms: 1000,
+        });
+
+        let heartbeat = identity.heartbeat();
+        assert_eq!(heartbeat.routing_info.sender, (1, 191).into());
+        assert!(heartbeat.routing_info.target.is_broadcast());
+        assert_eq!(heartbeat.msg_id, HEARTBEAT_ID);
+        assert_eq!(heartbeat.seq, 0);
+        assert_eq!(heartbeat.data[14], MAV_TYPE_ONBOARD_CONTROLLER);
+
+        assert_eq!(identity.heartbeat().seq, 1);
+    }
+}
@@ -0,0 +1,234 @@
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, time::Duration};
+use tokio::time::Instant;
+
+use crate::{endpoint::Name, mavlink};
+
+// A looping message repeats constantly, so only report it every so often.
+const REPORT_INTERVAL: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Settings {
+    /// How long a message is remembered to notice it coming back.
+    #[serde(default = "default_window_ms")]
+    pub window_ms: u64,
+    /// How long to drop messages of a sender arriving over a looping endpoint, disabled if not set.
+    #[serde(default)]
+    pub quarantine_ms: Option<u64>,
+}
+
+fn default_window_ms() -> u64 {
+    500
+}
+
+type Key = (mavlink::SysCompId, u8, u16);
+type Path = (mavlink::SysCompId, Name);
+
+struct Learned {
+    endpoint: Name,
+    last_seen: Instant,
+}
+
+/// Notices messages which come back to the router over a different endpoint than their sender
+/// is known on.
+pub struct LoopDetector {
+    window: Duration,
+    quarantine: Option<Duration>,
+    learned: HashMap<mavlink::SysCompId, Learned>,
+    recent: HashMap<Key, (Name, Instant)>,
+    last_pruned: Instant,
+    quarantined: HashMap<Path, Instant>,
+    reported: HashMap<Path, Instant>,
+}
+
+impl LoopDetector {
+    pub fn new(settings: &Settings) -> Self {
+        Self {
+            window: Duration::from_millis(settings.window_ms),
+            quarantine: settings.quarantine_ms.map(Duration::from_millis),
+            learned: HashMap::new(),
+            recent: HashMap::new(),
+            last_pruned: Instant::now(),
+            quarantined: HashMap::new(),
+            reported: HashMap::new(),
+        }
+    }
+
+    /// Returns whether the message should be dropped because it arrived over a looping path.
+    pub fn is_looping(&mut self, source: &Name, msg: &mavlink::Message, now: Instant) -> bool {
+        self.prune(now);
+
+        let sender = msg.routing_info.sender;
+        let path = (sender, source.clone());
+        if self
+            .quarantined
+            .get(&path)
+            .is_some_and(|until| now < *until)
+        {
+            return true;
+        }
+
+        let learned = self
+            .learned
+            .entry(sender)
+            .and_modify(|learned| {
+                // Senders can move to another endpoint, e.g. when a link goes down.
+                if learned.endpoint != *source
+                    && now.duration_since(learned.last_seen) >= self.window
+                {
+                    learned.endpoint = source.clone();
+                }
+            })
+            .or_insert_with(|| Learned {
+                endpoint: source.clone(),
+                last_seen: now,
+            });
+        if learned.endpoint == *source {
+            learned.last_seen = now;
+        }
+        let learned_endpoint = learned.endpoint.clone();
+
+        let key = (sender, msg.seq, msg.checksum);
+        let came_back = match self.recent.get(&key) {
+            Some((endpoint, seen)) => {
+                endpoint != source
+                    && *source != learned_endpoint
+                    && now.duration_since(*seen) < self.window
+            }
+            None => {
+                self.recent.insert(key, (source.clone(), now));
+                false
+            }
+        };
+        if !came_back {
+            return false;
+        }
+
+        let report_due = self
+            .reported
+            .get(&path)
+            .is_none_or(|reported| now.duration_since(*reported) >= REPORT_INTERVAL);
+        if report_due {
+            warn!(
+                endpoint = &**source;
+                "Routing loop detected: message from {} learned on '{}' came back on '{}'",
+                sender, learned_endpoint, source
+            );
+            self.reported.insert(path.clone(), now);
+        }
+
+        match self.quarantine {
+            Some(quarantine) => {
+                if report_due {
+                    warn!(
+                        endpoint = &**source;
+                        "[{}] Quarantining messages from {} for {:?}",
+                        source, sender, quarantine
+                    );
+                }
+                self.quarantined.insert(path, now + quarantine);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn prune(&mut self, now: Instant) {
+        if now.duration_since(self.last_pruned) < self.window {
+            return;
+        }
+        let window = self.window;
+        self.recent
+            .retain(|_, (_, seen)| now.duration_since(*seen) < window);
+        self.quarantined.retain(|_, until| now < *until);
+        self.reported
+            .retain(|_, reported| now.duration_since(*reported) < REPORT_INTERVAL);
+        self.last_pruned = now;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(seq: u8) -> mavlink::Message {
+        mavlink::Message {
+            routing_info: mavlink::RoutingInfo {
+                sender: (1, 1).into(),
+                target: (0, 0).into(),
+            },
+            msg_id: 0,
+            seq,
+            checksum: 42,
+            data: [0].into(),
+        }
+    }
+
+    fn detector(quarantine_ms: Option<u64>) -> LoopDetector {
+        LoopDetector::new(&Settings {
+            window_ms: 500,
+            quarantine_ms,
+        })
+    }
+
+    #[test]
+    fn test_message_coming_back_is_detected() {
+        let mut detector = detector(Some(1000));
+        let (vehicle, relay) = (Name::from("vehicle"), Name::from("relay"));
+        let now = Instant::now();
+
+        assert!(!detector.is_looping(&vehicle, &message(1), now));
+        assert!(detector.is_looping(&relay, &message(1), now));
+    }
+
+    #[test]
+    fn test_quarantine_drops_following_messages() {
+        let mut detector = detector(Some(1000));
+        let (vehicle, relay) = (Name::from("vehicle"), Name::from("relay"));
+        let now = Instant::now();
+
+        detector.is_looping(&vehicle, &message(1), now);
+        detector.is_looping(&relay, &message(1), now);
+
+        let later = now + Duration::from_millis(100);
+        assert!(detector.is_looping(&relay, &message(2), later));
+        assert!(!detector.is_looping(&vehicle, &message(2), later));
+
+        let expired = now + Duration::from_millis(1000);
+        assert!(!detector.is_looping(&relay, &message(3), expired));
+    }
+
+    #[test]
+    fn test_without_quarantine_messages_are_kept() {
+        let mut detector = detector(None);
+        let (vehicle, relay) = (Name::from("vehicle"), Name::from("relay"));
+        let now = Instant::now();
+
+        detector.is_looping(&vehicle, &message(1), now);
+        assert!(!detector.is_looping(&relay, &message(1), now));
+    }
+
+    #[test]
+    fn test_different_messages_are_not_loops() {
+        let mut detector = detector(Some(1000));
+        let (vehicle, relay) = (Name::from("vehicle"), Name::from("relay"));
+        let now = Instant::now();
+
+        assert!(!detector.is_looping(&vehicle, &message(1), now));
+        assert!(!detector.is_looping(&relay, &message(2), now));
+    }
+
+    #[test]
+    fn test_sender_can_move_to_another_endpoint() {
+        let mut detector = detector(Some(1000));
+        let (radio, lte) = (Name::from("radio"), Name::from("lte"));
+        let now = Instant::now();
+
+        assert!(!detector.is_looping(&radio, &message(1), now));
+
+        let later = now + Duration::from_millis(600);
+        assert!(!detector.is_looping(&lte, &message(2), later));
+        assert!(detector.is_looping(&radio, &message(2), later));
+    }
+}
@@ -0,0 +1,288 @@
+use crate::{
+    config,
+    endpoint::{EndpointTx, LinkStats, Name, QueueError},
+    log_error::LogError,
+    mavlink,
+    metrics::RouterMetrics,
+};
+use dedup::Deduplicator;
+use failover::Failover;
+use identity::Identity;
+use log::{debug, info};
+use loop_detection::LoopDetector;
+use std::{sync::Arc, time::Duration};
+use tokio::{
+    sync::{mpsc, oneshot},
+    time::Instant,
+};
+
+pub mod dedup;
+pub mod failover;
+pub mod identity;
+pub mod loop_detection;
+
+const HEARTBEAT_ID: u32 = 0;
+
+// How often the health of failover links is evaluated.
+const EVALUATION_PERIOD: Duration = Duration::from_secs(1);
+
+/// A message together with the name of the endpoint it was received on.
+pub type Routed = (Name, mavlink::Message);
+pub type RouterTx = mpsc::Sender<Routed>;
+
+/// Changes to the endpoints of a running router.
+pub enum Command {
+    AddEndpoint {
+        name: Name,
+        tx: EndpointTx,
+        link_stats: Arc<LinkStats>,
+        groups: Vec<String>,
+        enabled: bool,
+    },
+    RemoveEndpoint(Name),
+    /// Disabled endpoints are neither routed to nor from, but keep their place in the router.
+    SetEnabled(Name, bool),
+    /// Routes the messages already received and stops, releasing the queues of all endpoints.
+    Shutdown(oneshot::Sender<()>),
+    /// Replies with the number of endpoints, proving the router is still responsive.
+    Ping(oneshot::Sender<usize>),
+}
+
+struct RouterEndpoint {
+    name: Name,
+    tx: EndpointTx,
+    groups: Vec<String>,
+    enabled: bool,
+}
+
+// Endpoints without groups only share the implicit default group.
+fn share_group(a: &[String], b: &[String]) -> bool {
+    if a.is_empty() || b.is_empty() {
+        return a.is_empty() && b.is_empty();
+    }
+    a.iter().any(|g| b.contains(g))
+}
+
+pub struct Router {
+    msg_tx: RouterTx,
+    msg_rx: mpsc::Receiver<Routed>,
+    command_tx: mpsc::Sender<Command>,
+    command_rx: mpsc::Receiver<Command>,
+    endpoints: Vec<RouterEndpoint>,
+    deduplicator: Deduplicator,
+    failover: Failover,
+    identity: Option<Identity>,
+    loop_detector: Option<LoopDetector>,
+    metrics: Arc<RouterMetrics>,
+    // Once shutting down, messages wait for room in the queues instead of being dropped.
+    draining: bool,
+}
+
+impl Router {
+    pub fn new(settings: &config::Settings) -> Self {
+        // Create a channel for sending messages to the router
+        let (msg_tx, msg_rx) = mpsc::channel(128);
+        let (command_tx, command_rx) = mpsc::channel(16);
+
+        Self {
+            msg_tx,
+            msg_rx,
+            command_tx,
+            command_rx,
+            endpoints: Vec::new(),
+            deduplicator: Deduplicator::new(&settings.redundant_links),
+            failover: Failover::new(&settings.link_groups),
+            identity: settings
+                .identity
+                .as_ref()
+                .map(Identity::new)
+                .inspect(|identity| info!("Router identity is {}", identity.id())),
+            loop_detector: settings.loop_detection.as_ref().map(LoopDetector::new),
+            metrics: Default::default(),
+            draining: false,
+        }
+    }
+
+    pub fn tx(&self) -> RouterTx {
+        self.msg_tx.clone()
+    }
+
+    pub fn metrics(&self) -> Arc<RouterMetrics> {
+        self.metrics.clone()
+    }
+
+    pub fn commands(&self) -> mpsc::Sender<Command> {
+        self.command_tx.clone()
+    }
+
+    pub fn add_endpoint(
+        &mut self,
+        name: Name,
+        tx: EndpointTx,
+        link_stats: Arc<LinkStats>,
+        groups: Vec<String>,
+    ) {
+        self.failover.add_link_stats(&name, link_stats);
+        self.endpoints.push(RouterEndpoint {
+            name,
+            tx,
+            groups,
+            enabled: true,
+        });
+    }
+
+    fn set_enabled(&mut self, name: &Name, enabled: bool) {
+        for endpoint in self.endpoints.iter_mut().filter(|e| &e.name == name) {
+            endpoint.enabled = enabled;
+        }
+    }
+
+    // Returns false once the router should stop.
+    async fn handle_command(&mut self, command: Command) -> bool {
+        match command {
+            Command::AddEndpoint {
+                name,
+                tx,
+                link_stats,
+                groups,
+                enabled,
+            } => {
+                self.add_endpoint(name.clone(), tx, link_stats, groups);
+                self.set_enabled(&name, enabled);
+            }
+            Command::RemoveEndpoint(name) => self.endpoints.retain(|e| e.name != name),
+            Command::SetEnabled(name, enabled) => self.set_enabled(&name, enabled),
+            Command::Ping(reply) => {
+                let _ = reply.send(self.endpoints.len());
+            }
+            Command::Shutdown(done) => {
+                self.draining = true;
+                while let Ok((source, msg)) = self.msg_rx.try_recv() {
+                    self.route_msg(source, msg).await;
+                }
+                self.endpoints.clear();
+                let _ = done.send(());
+                return false;
+            }
+        }
+        true
+    }
+
+    pub fn start(mut self) {
+        tokio::spawn(async move {
+            self.route().await;
+        });
+    }
+
+    async fn route(&mut self) {
+        let mut evaluation = tokio::time::interval(EVALUATION_PERIOD);
+        let heartbeat_period = self
+            .identity
+            .as_ref()
+            .map_or(EVALUATION_PERIOD, Identity::interval);
+        let mut heartbeat = tokio::time::interval(heartbeat_period);
+        loop {
+            tokio::select! {
+                msg = self.msg_rx.recv() => match msg {
+                    Some((source, msg)) => self.route_msg(source, msg).await,
+                    None => break,
+                },
+                Some(command) = self.command_rx.recv() => {
+                    if !self.handle_command(command).await {
+                        break;
+                    }
+                }
+                _ = evaluation.tick() => self.failover.evaluate(Instant::now()),
+                _ = heartbeat.tick(), if self.identity.is_some() => self.send_heartbeat().await,
+            }
+        }
+    }
+
+    async fn send_heartbeat(&mut self) {
+        let msg = match &mut self.identity {
+            Some(identity) => identity.heartbeat(),
+            None => return,
+        };
+        for endpoint in self.endpoints.iter().filter(|e| e.enabled) {
+            endpoint
+                .tx
+                .send(msg.clone())
+                .await
+                .log_error_on(&endpoint.name, None);
+        }
+    }
+
+    async fn route_msg(&mut self, source: Name, msg: mavlink::Message) {
+        let now = Instant::now();
+        self.metrics.routed();
+        if self.deduplicator.is_duplicate(&source, &msg, now) {
+            self.metrics.duplicate();
+            debug!(
+                endpoint = &*source;
+                "[{}] Dropping duplicate message {} with seq {} from {}",
+                source, msg.msg_id, msg.seq, msg.routing_info.sender
+            );
+            return;
+        }
+        if let Some(loop_detector) = &mut self.loop_detector {
+            if loop_detector.is_looping(&source, &msg, now) {
+                self.metrics.looping();
+                return;
+            }
+        }
+        self.failover.record(&source, &msg, now);
+
+        let source = match self.endpoints.iter().find(|e| e.name == source) {
+            Some(source) if source.enabled => source,
+            _ => return,
+        };
+        for endpoint in &self.endpoints {
+            if !endpoint.enabled
+                || !share_group(&endpoint.groups, &source.groups)
+                || self.failover.is_standby(&endpoint.name, &msg)
+            {
+                continue;
+            }
+            if self.draining {
+                endpoint
+                    .tx
+                    .send(msg.clone())
+                    .await
+                    .log_error_on(&endpoint.name, None);
+                continue;
+            }
+            match endpoint.tx.forward(msg.clone()).await {
+                Err(QueueError::Full) => debug!(
+                    endpoint = &*endpoint.name;
+                    "[{}] Dropping message {}, the queue is full",
+                    endpoint.name, msg.msg_id
+                ),
+                res => {
+                    res.log_error_on(&endpoint.name, None);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn groups(groups: &[&str]) -> Vec<String> {
+        groups.iter().map(|g| g.to_string()).collect()
+    }
+
+    #[test]
+    fn test_share_group() {
+        let vehicle1 = groups(&["vehicle1"]);
+        let vehicle2 = groups(&["vehicle2"]);
+        let logger = groups(&["vehicle1", "vehicle2"]);
+
+        assert!(share_group(&vehicle1, &logger));
+        assert!(share_group(&vehicle2, &logger));
+        assert!(!share_group(&vehicle1, &vehicle2));
+        assert!(!share_group(&vehicle1, &[]));
+        assert!(share_group(&[], &[]));
+    }
+}
@@ -0,0 +1,586 @@
+use log::{error, info, warn};
+use std::{collections::HashMap, net::SocketAddr, sync::Arc, time::Duration};
+use tokio::sync::{mpsc, oneshot};
+
+use crate::{
+    config, control,
+    endpoint::{
+        target_database::TargetDatabase, Endpoint, EndpointSettings, LinkStats, RunningEndpoint,
+    },
+    mavlink,
+    metrics::Metrics,
+    router,
+};
+
+enum Request {
+    Reload(config::Settings),
+    Shutdown(Duration, oneshot::Sender<bool>),
+    Control(control::Request, oneshot::Sender<control::Response>),
+}
+
+/// Controls a running router.
+#[derive(Clone)]
+pub struct Handle {
+    request_tx: mpsc::Sender<Request>,
+    router_commands: mpsc::Sender<router::Command>,
+}
+
+impl Handle {
+    /// Applies the endpoints of the settings, keeping unchanged endpoints running.
+    pub async fn reload(&self, settings: config::Settings) {
+        if self
+            .request_tx
+            .send(Request::Reload(settings))
+            .await
+            .is_err()
+        {
+            warn!("Can't reload the configuration, the router is not running");
+        }
+    }
+
+    /// Stops receiving and sends everything already received, waiting at most for the timeout
+    /// before stopping the endpoints anyway. Returns whether all messages were sent.
+    pub async fn shutdown(&self, timeout: Duration) -> bool {
+        let (done_tx, done_rx) = oneshot::channel();
+        if self
+            .request_tx
+            .send(Request::Shutdown(timeout, done_tx))
+            .await
+            .is_err()
+        {
+            warn!("Can't shut down, the router is not running");
+            return false;
+        }
+        done_rx.await.unwrap_or(false)
+    }
+
+    /// Handles a request of the control API.
+    pub async fn control(&self, request: control::Request) -> control::Response {
+        let (response_tx, response_rx) = oneshot::channel();
+        if self
+            .request_tx
+            .send(Request::Control(request, response_tx))
+            .await
+            .is_err()
+        {
+            return control::Response::Error("The router is not running".to_string());
+        }
+        response_rx.await.unwrap_or_else(|_| {
+            control::Response::Error("The router stopped handling the request".to_string())
+        })
+    }
+
+    /// Checks that the router still handles requests, returning the number of endpoints it
+    /// routes between, or `None` if it didn't reply within the timeout.
+    pub async fn ping(&self, timeout: Duration) -> Option<usize> {
+        let ping = async {
+            let (reply_tx, reply_rx) = oneshot::channel();
+            self.router_commands
+                .send(router::Command::Ping(reply_tx))
+                .await
+                .ok()?;
+            reply_rx.await.ok()
+        };
+        tokio::time::timeout(timeout, ping).await.ok().flatten()
+    }
+}
+
+// What was changed on a running endpoint through the control API, which outlives a restart.
+struct RuntimeState {
+    static_routes: Vec<(mavlink::SysCompId, SocketAddr)>,
+    enabled: bool,
+}
+
+pub struct ManagedEndpoint {
+    settings: EndpointSettings,
+    running: RunningEndpoint,
+    link_stats: Arc<LinkStats>,
+    targets: Arc<TargetDatabase>,
+    enabled: bool,
+}
+
+impl ManagedEndpoint {
+    pub fn start(settings: EndpointSettings, endpoint: Endpoint) -> Self {
+        Self {
+            settings,
+            link_stats: endpoint.link_stats().clone(),
+            targets: endpoint.targets().clone(),
+            running: endpoint.start(),
+            enabled: true,
+        }
+    }
+
+    fn info(&self) -> control::EndpointInfo {
+        let stats = self.link_stats.snapshot();
+        control::EndpointInfo {
+            name: self.settings.name.clone(),
+            url: self.settings.kind.to_string(),
+            enabled: self.enabled,
+            targets: self.targets.target_count(),
+            received: stats.iter().map(|(_, s)| s.received).sum(),
+            lost: stats.iter().map(|(_, s)| s.lost).sum(),
+            invalid_senders: self.link_stats.invalid_senders(),
+        }
+    }
+}
+
+/// Owns the running endpoints and applies configuration changes to them.
+pub struct Supervisor {
+    settings: config::Settings,
+    endpoints: Vec<ManagedEndpoint>,
+    router_tx: router::RouterTx,
+    router_commands: mpsc::Sender<router::Command>,
+    deserializer: Arc<mavlink::Deserializer>,
+    link_stats_interval: Option<Duration>,
+    metrics: Arc<Metrics>,
+}
+
+impl Supervisor {
+    pub fn new(
+        settings: config::Settings,
+        endpoints: Vec<ManagedEndpoint>,
+        router: &router::Router,
+        deserializer: Arc<mavlink::Deserializer>,
+        link_stats_interval: Option<Duration>,
+        metrics: Arc<Metrics>,
+    ) -> Self {
+        Self {
+            settings,
+            endpoints,
+            router_tx: router.tx(),
+            router_commands: router.commands(),
+            deserializer,
+            link_stats_interval,
+            metrics,
+        }
+    }
+
+    pub fn start(mut self) -> Handle {
+        let (request_tx, request_rx) = mpsc::channel(1);
+        let router_commands = self.router_commands.clone();
+        tokio::spawn(async move {
+            self.run(request_rx).await;
+        });
+        Handle {
+            request_tx,
+            router_commands,
+        }
+    }
+
+    async fn run(&mut self, mut request_rx: mpsc::Receiver<Request>) {
+        let mut link_stats_interval =
+            tokio::time::interval(self.link_stats_interval.unwrap_or(Duration::from_secs(1)));
+        // The first tick completes immediately, when there is nothing to report yet.
+        link_stats_interval.tick().await;
+        let mut controllable = true;
+        loop {
+            tokio::select! {
+                request = request_rx.recv(), if controllable => match request {
+                    Some(Request::Reload(settings)) => self.reload(settings).await,
+                    Some(Request::Control(request, response)) => {
+                        let _ = response.send(self.control(request).await);
+                    }
+                    Some(Request::Shutdown(timeout, done)) => {
+                        let _ = done.send(self.shutdown(timeout).await);
+                        break;
+                    }
+                    // Without any handles left, the endpoints just keep running
+                    None => controllable = false,
+                },
+                _ = link_stats_interval.tick(), if self.link_stats_interval.is_some() => {
+                    for endpoint in &self.endpoints {
+                        endpoint.link_stats.log();
+                    }
+                }
+                else => break,
+            }
+        }
+    }
+
+    async fn reload(&mut self, settings: config::Settings) {
+        info!("Reloading configuration...");
+        if without_endpoints(&settings) != without_endpoints(&self.settings) {
+            warn!(
+                "Only changes to endpoints and ID locks are applied, the rest requires a restart"
+            );
+        }
+
+        let running: Vec<_> = self.endpoints.iter().map(|e| e.settings.clone()).collect();
+        let restart_all = settings.id_locks != self.settings.id_locks;
+        let (stop, start) = diff(&running, &settings.endpoints, restart_all);
+        // The other settings keep what was applied, so later reloads compare against that
+        self.settings.endpoints = settings.endpoints;
+        self.settings.id_locks = settings.id_locks;
+
+        // Endpoints restarted with new settings keep their static routes and enabled state
+        let mut states = HashMap::new();
+        for name in stop {
+            if let Some(state) = self.stop_endpoint(&name).await {
+                states.insert(name, state);
+            }
+        }
+        for settings in start {
+            let state = states.remove(&settings.name);
+            self.start_endpoint(settings, state).await;
+        }
+    }
+
+    async fn control(&mut self, request: control::Request) -> control::Response {
+        use control::{Request, Response};
+
+        let endpoint_name = match &request {
+            Request::List | Request::ResetStats { endpoint: None } => None,
+            Request::Routes { endpoint }
+            | Request::Enable { endpoint }
+            | Request::Disable { endpoint }
+            | Request::ResetStats {
+                endpoint: Some(endpoint),
+            } => Some(endpoint),
+            Request::AddRoute(route) | Request::RemoveRoute(route) => Some(&route.endpoint),
+        };
+        let index = match endpoint_name {
+            Some(name) => match self.endpoints.iter().position(|e| &e.settings.name == name) {
+                Some(index) => Some(index),
+                None => return Response::Error(format!("Unknown endpoint '{}'", name)),
+            },
+            None => None,
+        };
+
+        match (request, index) {
+            (Request::List, _) => {
+                Response::Endpoints(self.endpoints.iter().map(ManagedEndpoint::info).collect())
+            }
+            (Request::Routes { .. }, Some(index)) => Response::Routes(
+                self.endpoints[index]
+                    .targets
+                    .routes()
+                    .into_iter()
+                    .map(|route| control::RouteInfo {
+                        sys_id: route.id.sys_id(),
+                        comp_id: route.id.comp_id(),
+                        address: route.addr,
+                        last_seen_ms: route.last_seen.map(|d| d.as_millis() as u64),
+                    })
+                    .collect(),
+            ),
+            (Request::AddRoute(route), Some(index)) => {
+                let id: mavlink::SysCompId = (route.sys_id, route.comp_id).into();
+                if !self.endpoints[index]
+                    .targets
+                    .add_static_route(id, route.address)
+                {
+                    return Response::Error("The route already exists".to_string());
+                }
+                info!(
+                    endpoint = route.endpoint.as_str();
+                    "[{}] Added static route for {} to {}",
+                    route.endpoint, id, route.address
+                );
+                Response::Done
+            }
+            (Request::RemoveRoute(route), Some(index)) => {
+                let id: mavlink::SysCompId = (route.sys_id, route.comp_id).into();
+                if !self.endpoints[index]
+                    .targets
+                    .remove_static_route(id, route.address)
+                {
+                    return Response::Error("There is no such route".to_string());
+                }
+                info!(
+                    endpoint = route.endpoint.as_str();
+                    "[{}] Removed static route for {} to {}",
+                    route.endpoint, id, route.address
+                );
+                Response::Done
+            }
+            (Request::Enable { .. }, Some(index)) => self.set_enabled(index, true).await,
+            (Request::Disable { .. }, Some(index)) => self.set_enabled(index, false).await,
+            (Request::ResetStats { .. }, Some(index)) => {
+                self.endpoints[index].link_stats.reset();
+                Response::Done
+            }
+            (Request::ResetStats { .. }, None) => {
+                for endpoint in &self.endpoints {
+                    endpoint.link_stats.reset();
+                }
+                Response::Done
+            }
+            (_, None) => Response::Error("Missing endpoint".to_string()),
+        }
+    }
+
+    async fn set_enabled(&mut self, index: usize, enabled: bool) -> control::Response {
+        let endpoint = &mut self.endpoints[index];
+        endpoint.enabled = enabled;
+        let name = endpoint.running.name().clone();
+        let _ = self
+            .router_commands
+            .send(router::Command::SetEnabled(name.clone(), enabled))
+            .await;
+        info!(
+            endpoint = &*name;
+            "[{}] {} endpoint",
+            name,
+            if enabled { "Enabled" } else { "Disabled" }
+        );
+        control::Response::Done
+    }
+
+    async fn shutdown(&mut self, timeout: Duration) -> bool {
+        info!("Shutting down...");
+        for endpoint in &mut self.endpoints {
+            endpoint.running.stop_receiving().await;
+        }
+
+        // The router releases the endpoint queues once it has routed what was received, and the
+        // senders then flush what they held back. Mirrors send every copy right away, so they
+        // have nothing left to flush.
+        let (done_tx, done_rx) = oneshot::channel();
+        let drain = async {
+            if self
+                .router_commands
+                .send(router::Command::Shutdown(done_tx))
+                .await
+                .is_ok()
+            {
+                let _ = done_rx.await;
+            }
+            for endpoint in &mut self.endpoints {
+                endpoint.running.drained().await;
+            }
+        };
+        let drained = tokio::time::timeout(timeout, drain).await.is_ok();
+        if !drained {
+            warn!("Stopping endpoints before all messages were sent");
+        }
+
+        for endpoint in self.endpoints.drain(..) {
+            self.metrics.remove_endpoint(&endpoint.settings.name);
+            endpoint.running.stop().await;
+        }
+        info!("Shut down");
+        drained
+    }
+
+    async fn stop_endpoint(&mut self, name: &str) -> Option<RuntimeState> {
+        let index = self
+            .endpoints
+            .iter()
+            .position(|e| e.settings.name == name)?;
+        let endpoint = self.endpoints.remove(index);
+        let state = RuntimeState {
+            static_routes: endpoint
+                .targets
+                .routes()
+                .into_iter()
+                .filter(|route| route.last_seen.is_none())
+                .map(|route| (route.id, route.addr))
+                .collect(),
+            enabled: endpoint.enabled,
+        };
+        let name = endpoint.running.name().clone();
+        self.metrics.remove_endpoint(&name);
+        // Stop routing to the endpoint before its tasks go away
+        let _ = self
+            .router_commands
+            .send(router::Command::RemoveEndpoint(name.clone()))
+            .await;
+        endpoint.running.stop().await;
+        info!(endpoint = &*name; "[{}] Stopped endpoint", name);
+        Some(state)
+    }
+
+    async fn start_endpoint(&mut self, settings: EndpointSettings, state: Option<RuntimeState>) {
+        let (endpoint_tx, endpoint) = match Endpoint::from_settings(
+            settings.clone(),
+            self.router_tx.clone(),
+            self.deserializer.clone(),
+            &self.settings.id_locks,
+        ) {
+            Ok(endpoint) => endpoint,
+            Err(e) => {
+                error!(
+                    endpoint = settings.name.as_str();
+                    "[{}] Failed to create endpoint: {}", settings.name, e
+                );
+                return;
+            }
+        };
+
+        let enabled = state.as_ref().is_none_or(|state| state.enabled);
+        for &(id, addr) in state.iter().flat_map(|state| &state.static_routes) {
+            endpoint.targets().add_static_route(id, addr);
+        }
+
+        self.metrics.add_endpoint(endpoint.metrics().clone());
+        let command = router::Command::AddEndpoint {
+            name: endpoint.name().clone(),
+            tx: endpoint_tx,
+            link_stats: endpoint.link_stats().clone(),
+            groups: settings.groups.clone(),
+            enabled,
+        };
+        let _ = self.router_commands.send(command).await;
+        info!(
+            endpoint = settings.name.as_str();
+            "[{}] Started endpoint{}",
+            settings.name,
+            if enabled { "" } else { " disabled" }
+        );
+
+        let mut endpoint = ManagedEndpoint::start(settings, endpoint);
+        endpoint.enabled = enabled;
+        self.endpoints.push(endpoint);
+    }
+}
+
+fn without_endpoints(settings: &config::Settings) -> config::Settings {
+    config::Settings {
+        endpoints: Vec::new(),
+        id_locks: Vec::new(),
+        ..settings.clone()
+    }
+}
+
+// Returns the names of the endpoints to stop and the settings of the endpoints to start.
+fn diff(
+    running: &[EndpointSettings],
+    new: &[EndpointSettings],
+    restart_all: bool,
+) -> (Vec<String>, Vec<EndpointSettings>) {
+    let is_unchanged = |settings: &EndpointSettings, others: &[EndpointSettings]| {
+        !restart_all && others.iter().any(|other| other == settings)
+    };
+    let stop = running
+        .iter()
+        .filter(|settings| !is_unchanged(settings, new))
+        .map(|settings| settings.name.clone())
+        .collect();
+    let start = new
+        .iter()
+        .filter(|settings| !is_unchanged(settings, running))
+        .cloned()
+        .collect();
+    (stop, start)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::endpoint::transmitter::{self, udp};
+
+    fn endpoint(name: &str, port: u16) -> EndpointSettings {
+        EndpointSettings::new(
+            name,
+            transmitter::Settings::Udp(udp::Settings {
+                address: ([127, 0, 0, 1], port).into(),
+            }),
+        )
+    }
+
+    fn names(settings: &[EndpointSettings]) -> Vec<&str> {
+        settings.iter().map(|s| s.name.as_str()).collect()
+    }
+
+    #[test]
+    fn test_diff_keeps_unchanged_endpoints() {
+        let running = [endpoint("gcs", 14550), endpoint("vehicle", 14551)];
+        let new = [endpoint("gcs", 14550), endpoint("vehicle", 14552)];
+
+        let (stop, start) = diff(&running, &new, false);
+        assert_eq!(stop, vec!["vehicle"]);
+        assert_eq!(names(&start), vec!["vehicle"]);
+    }
+
+    #[test]
+    fn test_diff_adds_and_removes_endpoints() {
+        let running = [endpoint("gcs", 14550), endpoint("vehicle", 14551)];
+        let new = [endpoint("gcs", 14550), endpoint("logger", 14560)];
+
+        let (stop, start) = diff(&running, &new, false);
+        assert_eq!(stop, vec!["vehicle"]);
+        assert_eq!(names(&start), vec!["logger"]);
+    }
+
+    #[test]
+    fn test_diff_restarts_all_endpoints() {
+        let running = [endpoint("gcs", 14550)];
+        let new = [endpoint("gcs", 14550)];
+
+        let (stop, start) = diff(&running, &new, true);
+        assert_eq!(stop, vec!["gcs"]);
+        assert_eq!(names(&start), vec!["gcs"]);
+    }
+
+    #[tokio::test]
+    async fn test_reload_keeps_runtime_state_of_restarted_endpoints() {
+        let settings = config::Settings::new("does-not-exist.xml");
+        let router = router::Router::new(&settings);
+        let mut supervisor = Supervisor::new(
+            settings.clone(),
+            Vec::new(),
+            &router,
+            Arc::new(mavlink::Deserializer::new(Default::default())),
+            None,
+            Arc::new(Metrics::new(router.metrics())),
+        );
+        router.start();
+        supervisor.start_endpoint(endpoint("gcs", 0), None).await;
+
+        let route = control::Route {
+            endpoint: "gcs".to_string(),
+            sys_id: 1,
+            comp_id: 1,
+            address: ([127, 0, 0, 1], 14550).into(),
+        };
+        let request = control::Request::AddRoute(route);
+        assert_eq!(supervisor.control(request).await, control::Response::Done);
+        let request = control::Request::Disable {
+            endpoint: "gcs".to_string(),
+        };
+        assert_eq!(supervisor.control(request).await, control::Response::Done);
+
+        let changed = EndpointSettings {
+            groups: vec!["vehicle1".to_string()],
+            ..endpoint("gcs", 0)
+        };
+        supervisor
+            .reload(config::Settings {
+                endpoints: vec![changed.clone()],
+                ..settings
+            })
+            .await;
+
+        assert_eq!(supervisor.endpoints[0].settings, changed);
+        assert!(!supervisor.endpoints[0].enabled);
+        assert_eq!(supervisor.endpoints[0].targets.routes().len(), 1);
+        assert!(supervisor.shutdown(Duration::from_secs(1)).await);
+    }
+
+    #[tokio::test]
+    async fn test_reload_keeps_applied_settings() {
+        let settings = config::Settings::new("does-not-exist.xml");
+        let router = router::Router::new(&settings);
+        let mut supervisor = Supervisor::new(
+            settings.clone(),
+            Vec::new(),
+            &router,
+            Arc::new(mavlink::Deserializer::new(Default::default())),
+            None,
+            Arc::new(Metrics::new(router.metrics())),
+        );
+        router.start();
+
+        supervisor
+            .reload(config::Settings {
+                endpoints: vec![endpoint("gcs", 0)],
+                link_stats_interval_ms: Some(1000),
+                ..settings
+            })
+            .await;
+
+        assert_eq!(names(&supervisor.settings.endpoints), vec!["gcs"]);
+        assert_eq!(supervisor.settings.link_stats_interval_ms, None);
+        assert!(supervisor.shutdown(Duration::from_secs(1)).await);
+    }
+}
@@ -0,0 +1,152 @@
+use log::{debug, warn};
+use std::{io, os::unix::net::UnixDatagram, sync::Arc, time::Duration};
+
+use crate::Handle;
+
+/// Where the notifications are sent, either a path or an abstract socket name.
+enum Address {
+    Path(std::path::PathBuf),
+    #[cfg(target_os = "linux")]
+    Abstract(std::os::unix::net::SocketAddr),
+}
+
+impl Address {
+    fn parse(address: &str) -> io::Result<Self> {
+        match address.strip_prefix('@') {
+            #[cfg(target_os = "linux")]
+            Some(name) => {
+                use std::os::linux::net::SocketAddrExt;
+                std::os::unix::net::SocketAddr::from_abstract_name(name).map(Self::Abstract)
+            }
+            #[cfg(not(target_os = "linux"))]
+            Some(_) => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "Abstract sockets are only supported on Linux",
+            )),
+            None => Ok(Self::Path(address.into())),
+        }
+    }
+}
+
+/// Sends notifications to the service manager, following the `sd_notify` protocol.
+pub struct Notifier {
+    socket: UnixDatagram,
+    address: Address,
+}
+
+impl Notifier {
+    pub fn new(address: &str) -> io::Result<Self> {
+        let socket = UnixDatagram::unbound()?;
+        // The service manager reads notifications right away, so never wait for it
+        socket.set_nonblocking(true)?;
+        Ok(Self {
+            socket,
+            address: Address::parse(address)?,
+        })
+    }
+
+    /// Creates a notifier for the socket in `$NOTIFY_SOCKET`, if it is set.
+    pub fn from_env() -> io::Result<Option<Self>> {
+        std::env::var("NOTIFY_SOCKET")
+            .ok()
+            .filter(|address| !address.is_empty())
+            .map(|address| Self::new(&address))
+            .transpose()
+    }
+
+    pub fn notify(&self, state: &str) -> io::Result<()> {
+        debug!("Notifying service manager: {}", state.replace('\n', ", "));
+        match &self.address {
+            Address::Path(path) => self.socket.send_to(state.as_bytes(), path),
+            #[cfg(target_os = "linux")]
+            Address::Abstract(address) => self.socket.send_to_addr(state.as_bytes(), address),
+        }
+        .map(|_| ())
+    }
+
+    /// Reports that all endpoints are bound and the router is running.
+    pub fn ready(&self, endpoints: usize) -> io::Result<()> {
+        self.notify(&format!("READY=1\n{}", status(endpoints)))
+    }
+
+    pub fn stopping(&self) -> io::Result<()> {
+        self.notify("STOPPING=1")
+    }
+
+    /// Pings the router at the interval, and resets the watchdog of the service manager as long
+    /// as the router replies.
+    pub async fn watchdog(self: Arc<Self>, handle: Handle, interval: Duration) {
+        let mut ticks = tokio::time::interval(interval);
+        loop {
+            ticks.tick().await;
+            match handle.ping(interval).await {
+                Some(endpoints) => {
+                    if let Err(e) = self.notify(&format!("WATCHDOG=1\n{}", status(endpoints))) {
+                        warn!("Failed to notify the service manager: {}", e);
+                    }
+                }
+                None => warn!("The router is unresponsive, not resetting the watchdog"),
+            }
+        }
+    }
+}
+
+fn status(endpoints: usize) -> String {
+    format!("STATUS=Routing between {} endpoints", endpoints)
+}
+
+/// The interval to reset the watchdog at, half of the timeout in `$WATCHDOG_USEC` as recommended
+/// by systemd. Not set if the watchdog is disabled or meant for another process.
+pub fn watchdog_interval() -> Option<Duration> {
+    let pid = std::env::var("WATCHDOG_PID").ok();
+    if pid.is_some_and(|pid| pid.parse() != Ok(std::process::id())) {
+        return None;
+    }
+    std::env::var("WATCHDOG_USEC")
+        .ok()?
+        .parse()
+        .ok()
+        .filter(|&usec| usec > 0)
+        .map(|usec| Duration::from_micros(usec) / 2)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_notify_sends_datagram() -> io::Result<()> {
+        let dir = std::env::temp_dir().join(format!("mavlink-shouter-{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        let path = dir.join("notify.sock");
+        let _ = std::fs::remove_file(&path);
+        let service_manager = UnixDatagram::bind(&path)?;
+        service_manager.set_read_timeout(Some(Duration::from_secs(1)))?;
+
+        let notifier = Notifier::new(path.to_str().unwrap())?;
+        notifier.ready(3)?;
+
+        let mut buf = [0; 64];
+        let len = service_manager.recv(&mut buf)?;
+        assert_eq!(&buf[..len], b"READY=1\nSTATUS=Routing between 3 endpoints");
+        std::fs::remove_dir_all(&dir)
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_notify_abstract_socket() -> io::Result<()> {
+        use std::os::linux::net::SocketAddrExt;
+
+        let name = format!("mavlink-shouter-test-{}", std::process::id());
+        let address = std::os::unix::net::SocketAddr::from_abstract_name(&name)?;
+        let service_manager = UnixDatagram::bind_addr(&address)?;
+        service_manager.set_read_timeout(Some(Duration::from_secs(1)))?;
+
+        Notifier::new(&format!("@{}", name))?.stopping()?;
+
+        let mut buf = [0; 16];
+        let len = service_manager.recv(&mut buf)?;
+        assert_eq!(&buf[..len], b"STOPPING=1");
+        Ok(())
+    }
+}